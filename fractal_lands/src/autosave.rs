@@ -0,0 +1,207 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::map::Map;
+
+
+// recovery files are named after their map file, with this suffix
+const RECOVERY_SUFFIX: &str = ".recovery";
+
+// the recovery file of maps which were not saved yet
+const UNNAMED_RECOVERY_FILE: &str = "unnamed.recovery";
+
+
+/**
+ * Writes the map to a recovery file, usually in the maps directory, from time
+ * to time if it was changed since it was loaded or saved.
+ */
+pub struct Autosave {
+    // seconds between two autosaves
+    interval: f64,
+    time: f64,
+
+    // where the recovery files are written
+    directory: PathBuf,
+
+    // the map as it was loaded, saved or autosaved the last time
+    saved_content: Option<String>,
+}
+
+
+impl Autosave {
+
+    pub fn new(interval: f64) -> Autosave {
+        Autosave::with_directory(interval, "maps")
+    }
+
+
+    pub fn with_directory(interval: f64, directory: impl Into<PathBuf>) -> Autosave {
+        Autosave {
+            interval,
+            time: 0.0,
+            directory: directory.into(),
+            saved_content: None,
+        }
+    }
+
+
+    /**
+     * Takes the current map as unchanged, must be called after a map was loaded,
+     * saved or generated.
+     */
+    pub fn reset(&mut self, map: &Map) {
+        self.saved_content = map.to_file_content().ok();
+        self.time = 0.0;
+    }
+
+
+    /**
+     * @return the name of the written recovery file, if the map was autosaved
+     */
+    pub fn update(&mut self, map: &Map, dt: f64) -> std::io::Result<Option<String>> {
+        // the first map is taken as it is
+        if self.saved_content.is_none() {
+            self.reset(map);
+            return Ok(None);
+        }
+
+        self.time += dt;
+
+        if self.time < self.interval {
+            return Ok(None);
+        }
+
+        self.time = 0.0;
+
+        let content = map.to_file_content().map_err(std::io::Error::other)?;
+
+        if self.saved_content.as_ref() == Some(&content) {
+            return Ok(None);
+        }
+
+        let filename = recovery_file_name(&map.filename);
+        std::fs::write(self.directory.join(&filename), &content)?;
+        self.saved_content = Some(content);
+
+        Ok(Some(filename))
+    }
+}
+
+
+pub fn recovery_file_name(map_filename: &str) -> String {
+    if map_filename.is_empty() {
+        UNNAMED_RECOVERY_FILE.to_string()
+    }
+    else {
+        map_filename.to_string() + RECOVERY_SUFFIX
+    }
+}
+
+
+/**
+ * @return the map file which the recovery file belongs to, an empty name for unsaved maps
+ */
+pub fn map_file_name(recovery_filename: &str) -> Option<String> {
+    if recovery_filename == UNNAMED_RECOVERY_FILE {
+        return Some(String::new());
+    }
+
+    recovery_filename.strip_suffix(RECOVERY_SUFFIX)
+        .filter(|name| name.ends_with(".map"))
+        .map(|name| name.to_string())
+}
+
+
+/**
+ * Removes the recovery file of a map, e.g. because the map was just saved.
+ */
+pub fn remove_recovery_file(map_filename: &str) {
+    let path = Path::new("maps").join(recovery_file_name(map_filename));
+
+    if path.exists() {
+        if let Err(error) = std::fs::remove_file(&path) {
+            println!("Error: cannot remove {:?}: {}", path, error);
+        }
+    }
+}
+
+
+/**
+ * Looks for recovery files which are newer than their maps.
+ *
+ * @return recovery file and map file name of the newest one
+ */
+pub fn find_recovery_file() -> Option<(String, String)> {
+    let mut newest: Option<(SystemTime, String, String)> = None;
+
+    for entry in std::fs::read_dir("maps").ok()?.flatten() {
+        let recovery_filename = entry.file_name().to_string_lossy().to_string();
+
+        let map_filename = match map_file_name(&recovery_filename) {
+            None => continue,
+            Some(name) => name,
+        };
+
+        let modified = match entry.metadata().and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified,
+            Err(_) => continue,
+        };
+
+        // unsaved maps have no file, their recovery file is always newer
+        let map_modified =
+            if map_filename.is_empty() { None }
+            else { std::fs::metadata(Path::new("maps").join(&map_filename)).and_then(|metadata| metadata.modified()).ok() };
+
+        let is_newer = map_modified.is_none_or(|time| modified > time);
+        let is_newest = newest.as_ref().is_none_or(|(time, _, _)| modified > *time);
+
+        if is_newer && is_newest {
+            newest = Some((modified, recovery_filename, map_filename));
+        }
+    }
+
+    newest.map(|(_, recovery_filename, map_filename)| (recovery_filename, map_filename))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::MAP_GROUND_LAYER;
+
+
+    #[test]
+    fn test_recovery_file_names() {
+        assert_eq!(recovery_file_name("start.map"), "start.map.recovery");
+        assert_eq!(map_file_name("start.map.recovery"), Some("start.map".to_string()));
+        assert_eq!(map_file_name(&recovery_file_name("")), Some(String::new()));
+        assert_eq!(map_file_name("start.map"), None);
+        assert_eq!(map_file_name("notes.recovery"), None);
+    }
+
+
+    #[test]
+    fn test_autosave_only_changes() {
+        let mut map = Map::new("Autosave", "", "");
+        map.filename = "autosave_test.map".to_string();
+
+        let directory = std::env::temp_dir().join("fractal_lands_autosave_test");
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let mut autosave = Autosave::with_directory(10.0, &directory);
+        assert_eq!(autosave.update(&map, 0.0).unwrap(), None);
+        assert_eq!(autosave.update(&map, 20.0).unwrap(), None);
+
+        let mob = map.factory.create_mob(5, MAP_GROUND_LAYER, [10.0, 10.0], 0.0, 1.0);
+        map.add_object(MAP_GROUND_LAYER, mob);
+        assert_eq!(autosave.update(&map, 5.0).unwrap(), None);
+
+        let written = autosave.update(&map, 5.0);
+        let exists = directory.join("autosave_test.map.recovery").exists();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(written.unwrap(), Some("autosave_test.map.recovery".to_string()));
+        assert!(exists);
+        assert_eq!(autosave.update(&map, 20.0).unwrap(), None);
+    }
+}
@@ -0,0 +1,103 @@
+use vecmath::Vector2;
+
+
+// limits of the zoom factor
+pub const MIN_ZOOM: f64 = 0.25;
+pub const MAX_ZOOM: f64 = 2.0;
+
+
+/**
+ * The part of the map which is shown in the window. The camera position is the
+ * world position shown in the window center. World y coordinates are twice as
+ * large as screen y coordinates.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    pub position: Vector2<f64>,
+    pub zoom: f64,
+}
+
+
+impl Camera {
+
+    pub fn new(position: Vector2<f64>) -> Camera {
+        Camera {
+            position,
+            zoom: 1.0,
+        }
+    }
+
+
+    /**
+     * Centers the view on the given world position, at normal size. Game mode
+     * calls this each frame with the player position.
+     */
+    pub fn follow(&mut self, position: Vector2<f64>) {
+        self.position = position;
+        self.zoom = 1.0;
+    }
+
+
+    /**
+     * Moves the view by a distance in screen pixels.
+     */
+    pub fn pan(&mut self, dx: f64, dy: f64) {
+        self.position[0] += dx / self.zoom;
+        self.position[1] += dy * 2.0 / self.zoom;
+    }
+
+
+    /**
+     * Changes the zoom factor, so that the world position under the given screen
+     * position stays in place.
+     */
+    pub fn zoom_at(&mut self, factor: f64, screen_pos: &Vector2<f64>, window_center: &Vector2<f64>) {
+        let fixed = self.screen_to_world(screen_pos, window_center);
+
+        self.zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+
+        let moved = self.screen_to_world(screen_pos, window_center);
+        self.position[0] += fixed[0] - moved[0];
+        self.position[1] += fixed[1] - moved[1];
+    }
+
+
+    pub fn screen_to_world(&self, screen_pos: &Vector2<f64>, window_center: &Vector2<f64>) -> Vector2<f64> {
+        let rel_x = (screen_pos[0] - window_center[0]) / self.zoom;
+        let rel_y = (screen_pos[1] - window_center[1]) * 2.0 / self.zoom;
+
+        [self.position[0] + rel_x, self.position[1] + rel_y]
+    }
+
+
+    pub fn world_to_screen(&self, position: &Vector2<f64>, window_center: &Vector2<f64>) -> Vector2<f64> {
+        let rel_x = (position[0] - self.position[0]) * self.zoom;
+        let rel_y = (position[1] - self.position[1]) * 0.5 * self.zoom;
+
+        [window_center[0] + rel_x, window_center[1] + rel_y]
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn test_screen_world_round_trip() {
+        let center = [400.0, 300.0];
+        let mut camera = Camera::new([1000.0, 2000.0]);
+
+        assert_eq!(camera.screen_to_world(&center, &center), [1000.0, 2000.0]);
+        assert_eq!(camera.screen_to_world(&[500.0, 350.0], &center), [1100.0, 2100.0]);
+
+        camera.zoom_at(2.0, &[500.0, 350.0], &center);
+        assert_eq!(camera.zoom, 2.0);
+        assert_eq!(camera.screen_to_world(&[500.0, 350.0], &center), [1100.0, 2100.0]);
+        assert_eq!(camera.world_to_screen(&[1100.0, 2100.0], &center), [500.0, 350.0]);
+
+        camera.zoom_at(100.0, &center, &center);
+        assert_eq!(camera.zoom, MAX_ZOOM);
+    }
+}
@@ -0,0 +1,162 @@
+use serde::{Serialize, Deserialize};
+use vecmath::{Vector2, vec2_add, vec2_sub, vec2_scale, vec2_len, vec2_square_len};
+
+
+// collision radius of ships and creatures, in world coordinates
+pub const MOVER_RADIUS: f64 = 16.0;
+
+// share of the tile width which blocks movement, for objects without an explicit footprint
+pub const TILE_FOOTPRINT_FACTOR: f64 = 0.25;
+
+// obstacles with a larger radius might be missed by the nearby obstacle searches
+pub const MAX_BLOCKER_RADIUS: f64 = 256.0;
+
+
+/**
+ * How much room an object takes on the ground.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Footprint {
+    // derived from the size of the object's tile
+    FromTile,
+
+    // a fixed radius, 0 means the object does not block movement
+    Radius(f64),
+}
+
+
+/**
+ * A circular area on the map which cannot be entered.
+ */
+#[derive(Debug, Clone)]
+pub struct Blocker {
+    pub position: Vector2<f64>,
+    pub radius: f64,
+}
+
+
+/**
+ * @return true if a mover of the given radius can stand at this position
+ */
+pub fn is_free(position: Vector2<f64>, mover_radius: f64, blockers: &[Blocker]) -> bool {
+    blockers.iter().all(|blocker| !overlaps(position, mover_radius, blocker))
+}
+
+
+/**
+ * Moves from the start position by the given step. Blockers in the way push the
+ * mover aside, so it slides along their rim. If there is no way around, the 
+ * mover stays where it was.
+ */
+pub fn slide_move(from: Vector2<f64>, step: Vector2<f64>, mover_radius: f64, blockers: &[Blocker]) -> Vector2<f64> {
+
+    // movers which got stuck inside an obstacle, e.g. because it was placed onto
+    // them in the editor, must be able to leave it
+    let relevant: Vec<&Blocker> = blockers.iter().filter(|b| !overlaps(from, mover_radius, b)).collect();
+
+    let mut target = vec2_add(from, step);
+
+    // pushing out of one blocker can push into another, so repeat a few times
+    for _i in 0..4 {
+        let mut pushed = false;
+
+        for blocker in &relevant {
+            if overlaps(target, mover_radius, blocker) {
+                let min_distance = blocker.radius + mover_radius;
+                let away = vec2_sub(target, blocker.position);
+                let len = vec2_len(away);
+
+                let normal = if len > 0.0001 {
+                    vec2_scale(away, 1.0 / len)
+                } 
+                else {
+                    // exactly on the center, go back where we came from
+                    let back = vec2_sub(from, blocker.position);
+                    vec2_scale(back, 1.0 / vec2_len(back))
+                };
+
+                target = vec2_add(blocker.position, vec2_scale(normal, min_distance + 0.01));
+                pushed = true;
+            }
+        }
+
+        if !pushed {
+            return target;
+        }
+    }
+
+    if relevant.iter().all(|b| !overlaps(target, mover_radius, b)) {
+        target
+    }
+    else {
+        from
+    }
+}
+
+
+/**
+ * @return the farthest point on the line from start to destination which can be 
+ *         reached without running into a blocker
+ */
+pub fn clip_path(from: Vector2<f64>, to: Vector2<f64>, mover_radius: f64, blockers: &[Blocker]) -> Vector2<f64> {
+    let direction = vec2_sub(to, from);
+    let distance = vec2_len(direction);
+
+    let relevant: Vec<Blocker> = blockers.iter().filter(|b| !overlaps(from, mover_radius, b)).cloned().collect();
+
+    let sample_distance = 4.0;
+    let samples = (distance / sample_distance).ceil() as usize;
+    let mut last_free = from;
+
+    for i in 1 ..= samples {
+        let t = (i as f64 / samples as f64).min(1.0);
+        let point = vec2_add(from, vec2_scale(direction, t));
+
+        if !is_free(point, mover_radius, &relevant) {
+            break;
+        }
+
+        last_free = point;
+    }
+
+    last_free
+}
+
+
+fn overlaps(position: Vector2<f64>, mover_radius: f64, blocker: &Blocker) -> bool {
+    let min_distance = blocker.radius + mover_radius;
+    blocker.radius > 0.0 && vec2_square_len(vec2_sub(position, blocker.position)) < min_distance * min_distance
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn test_slide_along_blocker() {
+        let blockers = [Blocker { position: [100.0, 0.0], radius: 50.0 }];
+
+        // head on, the step ends in the blocker and is pushed back out
+        let pos = slide_move([30.0, 0.0], [10.0, 0.0], 10.0, &blockers);
+        assert!(is_free(pos, 10.0, &blockers));
+
+        // at an angle, the mover keeps going sideways
+        let pos = slide_move([40.0, -20.0], [10.0, 5.0], 10.0, &blockers);
+        assert!(is_free(pos, 10.0, &blockers));
+        assert!(pos[0] > 40.0);
+    }
+
+
+    #[test]
+    fn test_clip_path() {
+        let blockers = [Blocker { position: [100.0, 0.0], radius: 50.0 }];
+
+        let end = clip_path([0.0, 0.0], [200.0, 0.0], 10.0, &blockers);
+        assert!(end[0] <= 40.0 && end[0] > 30.0);
+
+        let end = clip_path([0.0, 100.0], [200.0, 100.0], 10.0, &blockers);
+        assert_eq!(end, [200.0, 100.0]);
+    }
+}
@@ -35,6 +35,11 @@ impl CreatureFactory {
     }
 
 
+    pub fn contains(&self, key: &str) -> bool {
+        self.prototypes.contains_key(key)
+    }
+
+
     pub fn create(&self, key: &str) -> Creature {
         let proto = self.prototypes.get(&key.to_string()).unwrap();
 
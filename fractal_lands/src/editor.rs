@@ -1,4 +1,6 @@
-use vecmath::Vector2;
+use vecmath::{Vector2, vec2_len, vec2_sub};
+use rand::Rng;
+use serde::Deserialize;
 
 use glium::winit::keyboard::Key;
 use glium::winit::keyboard::NamedKey;
@@ -6,18 +8,198 @@ use glium::Program;
 use glium::Frame;
 
 use crate::ui::{UI, UiController, UiComponent, TileSet, MouseButton, Button, ButtonState, ButtonEvent, MouseMoveEvent, ScrollEvent};
-use crate::map::{MAP_GROUND_LAYER, MAP_OBJECT_LAYER, MAP_CLOUD_LAYER};
+use crate::map::{Map, MapTransition, MobType, Visual, MAP_OBJECT_LAYER};
+use crate::collision::Footprint;
+use crate::map_generator::{GeneratorConfig, generate_map};
+use crate::map_file::list_map_files;
+use crate::editor_history::{EditHistory, EditChange, ObjectState};
+use crate::editor_brush::{BrushConfig, BrushMode, scatter, objects_to_erase};
+use crate::editor_inspector::{Inspector, edited_state, is_button};
+use crate::tile_palette::{TilePalette, matches_filter};
+use crate::autosave::{Autosave, find_recovery_file, remove_recovery_file};
 use crate::GameWorld;
+use crate::camera::Camera;
 use crate::sound::Sound;
 use crate::gl_support::BlendMode;
+use crate::particle_driver::{ParticleDriver, EmitterSettings};
 use crate::gl_support::draw_texture;
 use crate::calc_tile_position;
 use crate::screen_to_world_pos;
 
 
+// number of actions which can be undone, unless the editor config says otherwise
+const UNDO_DEPTH: usize = 100;
+
+// duplicates are placed this far from their originals
+const DUPLICATE_OFFSET: Vector2<f64> = [32.0, 32.0];
+
+// component ids of the file dialog. The file list uses ids from FILE_LIST_ID upwards
+const FILENAME_FIELD_ID: usize = 2000;
+const FILE_OK_ID: usize = 2001;
+const FILE_CANCEL_ID: usize = 2002;
+const OVERWRITE_YES_ID: usize = 2003;
+const OVERWRITE_NO_ID: usize = 2004;
+const FILE_LIST_ID: usize = 2100;
+
+// component ids of the transition destination dialog. The map list uses ids from DESTINATION_LIST_ID upwards
+const DESTINATION_MAP_FIELD_ID: usize = 3000;
+const DESTINATION_X_FIELD_ID: usize = 3001;
+const DESTINATION_Y_FIELD_ID: usize = 3002;
+const DESTINATION_FACING_FIELD_ID: usize = 3003;
+const DESTINATION_OK_ID: usize = 3004;
+const DESTINATION_CANCEL_ID: usize = 3005;
+const DESTINATION_LIST_ID: usize = 3100;
+
+// component ids of the dialog which offers to restore an autosaved map
+const RECOVERY_RESTORE_ID: usize = 5000;
+const RECOVERY_DISCARD_ID: usize = 5001;
+
+// filter field of the tile selector. The tile icons use the tile ids, this is above all of them
+const TILE_FILTER_FIELD_ID: usize = 100000;
+
+// how far Ctrl+arrow keys move the camera, in screen pixels
+const CAMERA_PAN_STEP: f64 = 64.0;
+
+// zoom factor per mouse wheel step
+const CAMERA_ZOOM_STEP: f64 = 1.1;
+
+// [ and ] change the brush radius by this much
+const BRUSH_RADIUS_STEP: f64 = 16.0;
+
+// seconds between two autosaves of a changed map
+const AUTOSAVE_INTERVAL: f64 = 60.0;
+
+// catchment radius of new transitions, and how much the mouse wheel changes it
+const TRANSITION_RADIUS: f64 = 120.0;
+const TRANSITION_RADIUS_STEP: f64 = 8.0;
+
+// the object which carries a new particle emitter, if no tile is on the cursor
+const PARTICLE_GENERATOR_TILE: usize = 212;
+
+
 pub struct MapEditor {
     pub selected_tile_id: usize,
     pub show_editor_keys: bool,
+
+    // last error, shown until the next successful action
+    pub error_message: Option<String>,
+
+    pub history: EditHistory,
+
+    // world position where the left mouse button was pressed
+    press_position: Option<Vector2<f64>>,
+    drag: Drag,
+
+    // screen position of the mouse while the middle button drags the camera
+    pan_position: Option<Vector2<f64>>,
+
+    // copied objects, kept when another map is loaded
+    clipboard: Vec<ClipboardItem>,
+
+    file_dialog: Option<FileDialog>,
+
+    // in transition mode, mouse and keys edit the map transitions instead of the objects
+    transition_mode: bool,
+    selected_transition: Option<usize>,
+    destination_dialog: Option<DestinationDialog>,
+
+    inspector: Inspector,
+
+    tile_selector: Option<TileSelector>,
+
+    // recently used and favorite tiles, saved between sessions
+    palette: TilePalette,
+
+    brush_mode: BrushMode,
+    brush: BrushConfig,
+
+    autosave: Autosave,
+    recovery_dialog: Option<RecoveryDialog>,
+}
+
+
+/**
+ * An autosaved map which is newer than its map file, and the map file it belongs to.
+ * The map file name is empty for maps which were never saved.
+ */
+struct RecoveryDialog {
+    recovery_file: String,
+    map_filename: String,
+}
+
+
+#[derive(PartialEq)]
+enum FileDialogMode {
+    Open,
+    Save,
+}
+
+
+/**
+ * State of the open/save dialog. The UI components are rebuilt from this
+ * whenever it changes.
+ */
+struct FileDialog {
+    mode: FileDialogMode,
+    files: Vec<String>,
+    filename: String,
+
+    // the file exists, the user must confirm to overwrite it
+    confirm_overwrite: bool,
+}
+
+
+/**
+ * State of the dialog to set the destination of a transition. The
+ * values are kept as typed, they are checked when the dialog is confirmed.
+ */
+struct DestinationDialog {
+    transition: usize,
+    files: Vec<String>,
+    to_map: String,
+    x: String,
+    y: String,
+    facing: String,
+}
+
+
+/**
+ * State of the tile selector, which shows the tiles of one tileset
+ * whose names match the filter.
+ */
+struct TileSelector {
+    tileset_id: usize,
+    filter: String,
+}
+
+
+/**
+ * A copied map object. The offset is relative to the center of all copied objects.
+ */
+struct ClipboardItem {
+    offset: Vector2<f64>,
+    visual: Visual,
+    footprint: Footprint,
+}
+
+
+/**
+ * What a mouse drag with the left button does
+ */
+enum Drag {
+    None,
+
+    // moves the selected objects. Keeps their state before the drag, for the undo history
+    Objects { start_state: Vec<(u64, ObjectState)>, last_position: Vector2<f64> },
+
+    // selects all objects in the rectangle
+    Rubberband { start: Vector2<f64> },
+
+    // moves a map transition. Keeps the transitions before the drag, for the undo history
+    Transition { index: usize, start_transitions: Vec<MapTransition>, last_position: Vector2<f64> },
+
+    // paints or erases objects. All changes of one stroke are undone together
+    Brush { changes: Vec<EditChange>, last_position: Vector2<f64> },
 }
 
 
@@ -32,8 +214,39 @@ impl UiController for MapEditor {
         // trigered it will consume the event. Events which are not
         // consumed by the UI will be handed to the game core
 
+        let mouse_pos = screen_to_world_pos(ui, &world.camera, &ui.context.mouse_state.position);
+
+        // the inspector panel is not part of the UI tree, it gets its events first
+        if self.inspector.takes_event(event) {
+            ui.update_input_state(event);
+            self.handle_inspector_event(event, world);
+            return true;
+        }
+
+        if event.args.state == ButtonState::Press && event.args.button == Button::Mouse(MouseButton::Left) {
+            self.inspector.release_focus();
+        }
+
+        // the middle mouse button drags the camera
+        if event.args.button == Button::Mouse(MouseButton::Middle) {
+            self.pan_position =
+                if event.args.state == ButtonState::Press { Some(ui.context.mouse_state.position) } else { None };
+            return true;
+        }
+
         let comp = ui.handle_button_event(&event);
 
+        if event.args.state == ButtonState::Press && comp.is_none() &&
+           event.args.button == Button::Mouse(MouseButton::Left) {
+            self.press_position = Some(mouse_pos);
+
+            if self.brush_mode != BrushMode::Off && !self.transition_mode &&
+               !self.refuse_locked(&world.map, world.map.selected_layer) {
+                let changes = self.brush_dab(world, &mouse_pos);
+                self.drag = Drag::Brush { changes, last_position: mouse_pos };
+            }
+        }
+
         if event.args.state == ButtonState::Release {
 
             match comp {
@@ -42,24 +255,60 @@ impl UiController for MapEditor {
                     println!("button pressed {:?}", event.args.button);                     
                     
                     if event.args.button == Button::Mouse(MouseButton::Left) {
+                        self.press_position = None;
+
+                        match std::mem::replace(&mut self.drag, Drag::None) {
+                            Drag::None => {},
+                            Drag::Objects { start_state, .. } => {
+                                // end of a drag, the dragged objects stay selected
+                                let map = &world.map;
+                                self.history.commit_modifications(map, map.selected_layer, start_state);
+                                return true;
+                            },
+                            Drag::Rubberband { start } => {
+                                let add = ui.context.keyboard_state.shift_pressed;
+                                world.map.select_in_rect(&start, &mouse_pos, add);
+                                return true;
+                            },
+                            Drag::Transition { start_transitions, .. } => {
+                                self.history.commit_transitions(&world.map, start_transitions);
+                                return true;
+                            },
+                            Drag::Brush { changes, .. } => {
+                                self.history.push(changes);
+                                return true;
+                            },
+                        }
+
+                        if self.transition_mode {
+                            self.selected_transition = world.map.find_transition(&mouse_pos);
+                            return true;
+                        }
+
+                        if self.refuse_locked(&world.map, world.map.selected_layer) {
+                            return true;
+                        }
+
                         let id = self.selected_tile_id;
 
                         if id == 0 {
-                            let ok = self.select_nearest_item(ui, world);
+                            let add = ui.context.keyboard_state.shift_pressed;
+                            let ok = self.select_nearest_item(ui, world, add);
                             return ok;
                         }
                         else {
-                            let pos = screen_to_world_pos(&ui, &world.map.player_position(), 
+                            let pos = screen_to_world_pos(ui, &world.camera, 
                                                           &ui.context.mouse_state.position);
                             world.speaker.play(Sound::Click, 0.5);
                             println!("creating map object {} at {:?}", id, pos);
                             
                             let map = &mut world.map;
                             let layer = map.selected_layer;
-                            let height = world.layer_tileset[layer].tiles_by_id.get(&id).unwrap().foot[1];
-                            let mob = map.factory.create_mob(id, layer, pos, height, 1.0);
-                            let mob_id = mob.uid;
-                            map.layers[layer].insert(mob_id, mob);
+                            let tileset_id = map.layer_info[layer].tileset_id;
+                            let height = world.layer_tileset[tileset_id].tiles_by_id.get(&id).unwrap().foot[1];
+                            let mob = map.factory.create_mob(id, tileset_id, pos, height, 1.0);
+                            self.history.push_insert(layer, mob.uid);
+                            map.add_object(layer, mob);
 
                             return true;
                         }
@@ -69,6 +318,10 @@ impl UiController for MapEditor {
 
                         // close dialogs
                         ui.root.head.clear();
+                        self.file_dialog = None;
+                        self.destination_dialog = None;
+                        self.tile_selector = None;
+                        self.recovery_dialog = None;
 
                         if self.selected_tile_id == 0 {
                             // nothing on cursor, center map?
@@ -83,104 +336,220 @@ impl UiController for MapEditor {
                         self.show_editor_keys = !self.show_editor_keys;
                     }        
 
-                    if event.args.button == Button::Keyboard(Key::Character("1".into())) {
-                        world.map.selected_layer = MAP_GROUND_LAYER;
-                        self.selected_tile_id = 0;
-                    }        
+                    // keys 1 to 7 select the map layers
+                    for layer in 0..world.map.layers.len() {
+                        let key = (layer + 1).to_string();
+                        if event.args.button == Button::Keyboard(Key::Character(key.as_str().into())) {
+                            world.map.selected_layer = layer;
+                            world.map.selection.clear();
+                            self.selected_tile_id = 0;
+                        }
+                    }
 
-                    if event.args.button == Button::Keyboard(Key::Character("2".into())) {                        
-                        world.map.selected_layer = MAP_OBJECT_LAYER;
-                        self.selected_tile_id = 0;
-                    }        
+                    // Ctrl+arrow keys pan the camera, before any other Ctrl key handling
+                    if self.handle_camera_key(ui, world, &event.args.button) {
+                        return true;
+                    }
 
-                    if event.args.button == Button::Keyboard(Key::Character("3".into())) {                        
-                        world.map.selected_layer = MAP_CLOUD_LAYER;
-                        self.selected_tile_id = 0;
-                    }        
+                    if ui.context.keyboard_state.ctrl_pressed {
+                        if let Button::Keyboard(Key::Character(key)) = &event.args.button {
+                            if self.handle_ctrl_key(ui, world, key.as_str()) {
+                                return true;
+                            }
+                        }
+                    }
+
+                    if event.args.button == Button::Keyboard(Key::Character("t".into())) {
+                        self.transition_mode = !self.transition_mode;
+                        self.selected_transition = None;
+                        world.map.selection.clear();
+                        return true;
+                    }
+
+                    if event.args.button == Button::Keyboard(Key::Character("i".into())) {
+                        self.inspector.toggle(ui, &world.map);
+                        return true;
+                    }
+
+                    if event.args.button == Button::Keyboard(Key::Character("h".into())) {
+                        let flags = &mut world.map.layer_flags[world.map.selected_layer];
+                        flags.hidden = !flags.hidden;
+                        return true;
+                    }
+
+                    if event.args.button == Button::Keyboard(Key::Character("k".into())) {
+                        let flags = &mut world.map.layer_flags[world.map.selected_layer];
+                        flags.locked = !flags.locked;
+
+                        // the selection can't be changed anymore
+                        world.map.selection.clear();
+                        self.error_message = None;
+                        return true;
+                    }
+
+                    if event.args.button == Button::Keyboard(Key::Character("b".into())) {
+                        self.brush_mode = self.brush_mode.next();
+                        return true;
+                    }
+
+                    if event.args.button == Button::Keyboard(Key::Character("B".into())) {
+                        // a new set of brush tiles can be picked in the tile selector
+                        self.brush.tiles.clear();
+                        return true;
+                    }
+
+                    if event.args.button == Button::Keyboard(Key::Character("[".into())) {
+                        self.brush.radius = (self.brush.radius - BRUSH_RADIUS_STEP).max(BRUSH_RADIUS_STEP);
+                        return true;
+                    }
+
+                    if event.args.button == Button::Keyboard(Key::Character("]".into())) {
+                        self.brush.radius += BRUSH_RADIUS_STEP;
+                        return true;
+                    }
+
+                    if self.transition_mode && self.handle_transition_key(ui, world, &event.args.button, mouse_pos) {
+                        return true;
+                    }
 
                     let step = if ui.context.keyboard_state.shift_pressed {8.0} else {1.0};
 
                     if event.args.button == Button::Keyboard(Key::Named(NamedKey::ArrowRight)) {                        
-                        world.map.move_selected_object(step, 0.0);
+                        self.modify_selected(&mut world.map, |map| map.move_selected_objects(step, 0.0));
                     }        
 
                     if event.args.button == Button::Keyboard(Key::Named(NamedKey::ArrowLeft)) {                        
-                        world.map.move_selected_object(-step, 0.0);
+                        self.modify_selected(&mut world.map, |map| map.move_selected_objects(-step, 0.0));
                     }        
 
                     if event.args.button == Button::Keyboard(Key::Named(NamedKey::ArrowUp)) {  
-                        world.map.move_selected_object(0.0, -step);
+                        self.modify_selected(&mut world.map, |map| map.move_selected_objects(0.0, -step));
                     }        
 
                     if event.args.button == Button::Keyboard(Key::Named(NamedKey::ArrowDown)) {
-                        world.map.move_selected_object(0.0, step);
+                        self.modify_selected(&mut world.map, |map| map.move_selected_objects(0.0, step));
                     }        
 
 
                     if event.args.button == Button::Keyboard(Key::Named(NamedKey::Space)) {
-                        let set = &world.layer_tileset[world.map.selected_layer];
-                        let cont = self.make_tile_selector(&ui, set);
-                        ui.root.head.add_child(cont);
+                        let tileset_id = world.map.layer_info[world.map.selected_layer].tileset_id;
+                        self.tile_selector = Some(TileSelector { tileset_id, filter: String::new() });
+                        self.show_tile_selector(ui, world);
                     }        
 
                     if event.args.button == Button::Keyboard(Key::Character("a".into())) {
-                        let map = &mut world.map;
-                        map.apply_to_selected_mob(|mob| {mob.visual.blend = BlendMode::Add;});
+                        self.modify_selected(&mut world.map, |map| {
+                            map.apply_to_selected_mobs(|mob| {mob.visual.blend = BlendMode::Add;});
+                        });
                     }
 
                     if event.args.button == Button::Keyboard(Key::Character("c".into())) {
-                        let map = &mut world.map;
-                        let object = map.layers[map.selected_layer].get_mut(&map.selected_item);
+                        // the color choice starts with the color of the first selected object
+                        let map = &world.map;
+                        let object = map.selection.first().and_then(|uid| map.layers[map.selected_layer].get(uid));
                         match object {
                             None => {},
                             Some(mob) => {
                                 let color_choice = ui.make_color_choice(100, 100, 256, 256, 1000, mob.visual.color);
+                                self.tile_selector = None;
+                                ui.root.head.clear();
                                 ui.root.head.add_child(color_choice);
                             }
                         }
                     }        
 
-                    if event.args.button == Button::Keyboard(Key::Named(NamedKey::Delete)) {
+                    if event.args.button == Button::Keyboard(Key::Named(NamedKey::Delete)) &&
+                       !self.refuse_locked(&world.map, world.map.selected_layer) {
                         let map = &mut world.map;
-                        let mob = map.layers[map.selected_layer].get(&map.selected_item);
-
-                        match mob {
-                            None => {}
-                            Some(mob) => {
-                                let uid = mob.uid;
-                                let layer = &mut map.layers[map.selected_layer];
-                                layer.remove(&uid);
-                            }
-                        }
+                        let selection = std::mem::take(&mut map.selection);
+                        self.history.remove_objects(map, map.selected_layer, &selection);
                     }        
 
                     if event.args.button == Button::Keyboard(Key::Character("l".into())) {
-                        world.map.load("start.map");
+                        self.open_file_dialog(ui, world, FileDialogMode::Open);
                     }
 
                     if event.args.button == Button::Keyboard(Key::Character("m".into())) {
-                        let map = &mut world.map;
-                        map.apply_to_selected_mob(|mob| {mob.visual.blend = BlendMode::Blend;});
+                        self.modify_selected(&mut world.map, |map| {
+                            map.apply_to_selected_mobs(|mob| {mob.visual.blend = BlendMode::Blend;});
+                        });
+                    }
+
+                    if event.args.button == Button::Keyboard(Key::Character("n".into())) {
+                        // a generator.ron in the maps directory overrides the default settings
+                        let mut config = 
+                            if std::path::Path::new("maps/generator.ron").exists() {
+                                GeneratorConfig::load("generator.ron").unwrap_or_else(|error| {
+                                    println!("Error in generator.ron: {}", error);
+                                    GeneratorConfig::default()
+                                })
+                            }
+                            else {
+                                GeneratorConfig::default()
+                            };
+                        config.remove_unknown_tiles(|tileset_id, tile_id| world.layer_tileset[tileset_id].tiles_by_id.contains_key(&tile_id));
+                        config.seed = world.rng.gen::<u32>() as u64;
+
+                        generate_map(&mut world.map, &config, &mut world.rng);
+                        world.map.filename.clear();
+                        world.reload_map_textures();
+                        world.camera = Camera::new(world.map.player_position());
+                        self.autosave.reset(&world.map);
+                        self.history.clear();
+                        self.selected_transition = None;
+                        self.error_message = None;
                     }
 
                     if event.args.button == Button::Keyboard(Key::Character("p".into())) {
-                        let pos = screen_to_world_pos(&ui, &world.map.player_position(), 
+                        let pos = screen_to_world_pos(ui, &world.camera, 
                                                       &ui.context.mouse_state.position);
-                        place_particle_generator(world, pos);
+                        if !self.refuse_locked(&world.map, MAP_OBJECT_LAYER) {
+                            // the tile on the cursor carries the emitter, if there is one
+                            let tile_id = if self.selected_tile_id == 0 { PARTICLE_GENERATOR_TILE } else { self.selected_tile_id };
+
+                            match place_particle_generator(world, tile_id, pos) {
+                                Ok(uid) => self.history.push_insert(MAP_OBJECT_LAYER, uid),
+                                Err(message) => self.error_message = Some(message),
+                            }
+                        }
                     }
 
                     if event.args.button == Button::Keyboard(Key::Character("s".into())) {
-                        world.map.save("test.map").unwrap();
+                        self.open_file_dialog(ui, world, FileDialogMode::Save);
                     }        
                 },
                 Some(comp) => {
                     let id = comp.get_id();
                     // let id = data[0];
 
+                    // while a dialog is open, it is the only component
+                    if self.recovery_dialog.is_some() {
+                        self.handle_recovery_dialog_event(ui, world, id);
+                        return true;
+                    }
+
+                    if self.file_dialog.is_some() {
+                        let text = comp.get_text_result();
+                        self.handle_file_dialog_event(ui, world, id, text, &event.args.button);
+                        return true;
+                    }
+
+                    if self.destination_dialog.is_some() {
+                        let text = comp.get_text_result();
+                        self.handle_destination_dialog_event(ui, world, id, text, &event.args.button);
+                        return true;
+                    }
+
+                    if self.tile_selector.is_some() {
+                        let text = comp.get_text_result();
+                        self.handle_tile_selector_event(ui, world, id, text, &event.args.button);
+                        return true;
+                    }
+
                     if id == 1000 {
                         // this was the color choice box
                         
-                        if world.map.has_selection {
+                        if world.map.has_selection() {
                         
                             let result = comp.get_numeric_result();
                             let r = result[0];
@@ -189,30 +558,14 @@ impl UiController for MapEditor {
                             let a = result[3];
                             println!("selected color is {:02x}{:02x}{:02x}{:02x}", r, g, b, a);
 
-                            let map = &mut world.map;
-                            let object = map.layers[map.selected_layer].get_mut(&map.selected_item);
-                            match object {
-                                None => {},
-                                Some(mob) => {
-                                    mob.visual.color = [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, a as f32 / 255.0]
-                                }
-                            }
+                            let color = [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, a as f32 / 255.0];
+                            self.modify_selected(&mut world.map, |map| {
+                                map.apply_to_selected_mobs(|mob| {mob.visual.color = color;});
+                            });
                         }
 
                         return true;
                     }
-                    else {
-                        // must have been the tile selector
-                        println!("Selected tile id={}", id);
-
-                        if id > 0 {
-                            world.speaker.play(Sound::Click, 0.5);
-                            self.selected_tile_id = id;
-                            ui.root.head.clear();
-    
-                            return true;
-                        }
-                    }
                 }
             }
         }
@@ -226,14 +579,47 @@ impl UiController for MapEditor {
      */
     fn handle_scroll_event(&mut self, ui: &mut UI, event: &ScrollEvent, world: &mut Self::Appdata) -> bool {
 
+        if self.inspector.contains(&ui.context.mouse_state.position) {
+            return true;
+        }
+
+        if ui.context.keyboard_state.ctrl_pressed {
+            world.camera.zoom_at(CAMERA_ZOOM_STEP.powf(event.dy), &ui.context.mouse_state.position, &ui.window_center());
+            return true;
+        }
+
         let comp = ui.handle_scroll_event(&event);
 
         match comp {
             None => {
-                let pos = screen_to_world_pos(ui, &world.map.player_position(), 
+                let pos = screen_to_world_pos(ui, &world.camera, 
                                               &ui.context.mouse_state.position);
 
                 let map = &mut world.map;
+
+                if self.transition_mode {
+                    // resizes the selected transition, or the one under the mouse
+                    let option = self.selected_transition.or_else(|| map.find_transition(&pos));
+                    self.history.modify_transitions(map, |transitions| {
+                        if let Some(transit) = option.and_then(|index| transitions.get_mut(index)) {
+                            transit.rad = (transit.rad + TRANSITION_RADIUS_STEP * event.dy).max(TRANSITION_RADIUS_STEP);
+                        }
+                    });
+                    return true;
+                }
+
+                if self.refuse_locked(map, map.selected_layer) {
+                    return true;
+                }
+
+                // the whole selection is scaled, if there is one
+                if map.has_selection() {
+                    self.modify_selected(map, |map| {
+                        map.apply_to_selected_mobs(|mob| {mob.visual.scale += 0.05 * event.dy;});
+                    });
+                    return true;
+                }
+
                 let option = map.find_nearest_object(map.selected_layer, &pos, 100.0, 0);
         
                 match option {
@@ -241,9 +627,12 @@ impl UiController for MapEditor {
                         println!("Found no object at {}, {}", pos[0], pos[1]);
                     },
                     Some(id) => {
-                        let mob = map.layers[map.selected_layer].get_mut(&id).unwrap();
-                        println!("Found object {} at scale {}", mob.uid, mob.visual.scale);
-                        mob.visual.scale += 0.05 * event.dy;
+                        let layer = map.selected_layer;
+                        self.history.modify_objects(map, layer, &[id], |map| {
+                            let mob = map.layers[layer].get_mut(&id).unwrap();
+                            println!("Found object {} at scale {}", mob.uid, mob.visual.scale);
+                            mob.visual.scale += 0.05 * event.dy;
+                        });
                     }
                 }
             },
@@ -259,27 +648,75 @@ impl UiController for MapEditor {
 
         ui.handle_mouse_move_event(event);
 
-        let player_position = &world.map.player_position();
         let mp = &ui.context.mouse_state.position;
-        let pos = screen_to_world_pos(&ui, player_position, mp);
+        let pos = screen_to_world_pos(ui, &world.camera, mp);
+
+        if let Some(last) = self.pan_position {
+            world.camera.pan(last[0] - mp[0], last[1] - mp[1]);
+            self.pan_position = Some(*mp);
+            return true;
+        }
+
+        // the brush works the same with and without a tile on the cursor
+        if let Drag::Brush { last_position, .. } = &self.drag {
+            let distance = vec2_len(vec2_sub(pos, *last_position));
+
+            if distance >= self.brush.radius * 0.5 {
+                let new_changes = self.brush_dab(world, &pos);
+
+                if let Drag::Brush { changes, last_position } = &mut self.drag {
+                    changes.extend(new_changes);
+                    *last_position = pos;
+                }
+            }
+            return true;
+        }
 
         // Dragging?
-        if ui.context.mouse_state.left_pressed {
+        if ui.context.mouse_state.left_pressed && self.selected_tile_id == 0 {
             let map = &mut world.map; 
-            let option = map.find_nearest_object(map.selected_layer, &pos, 100.0, 0);
 
-            match option {
-                None => {
-                    // Nothing to do
-                },
-                Some(id) => {
-                    if map.selected_item == id {
+            // the object under the mouse at the start of the drag decides what the drag does
+            if let (Drag::None, Some(start), true) = (&self.drag, self.press_position, self.transition_mode) {
+                self.press_position = None;
+
+                if let Some(index) = map.find_transition(&start) {
+                    self.selected_transition = Some(index);
+                    let start_transitions = map.transitions.clone();
+                    self.drag = Drag::Transition { index, start_transitions, last_position: start };
+                }
+            }
+
+            // objects of locked layers can't be selected or moved
+            let start = self.press_position.take().filter(|_start| !self.refuse_locked(map, map.selected_layer));
+
+            if let (Drag::None, Some(start)) = (&self.drag, start) {
+                let option = map.find_nearest_object(map.selected_layer, &start, 100.0, 0);
 
-                        let mob = &mut map.layers[map.selected_layer].get_mut(&id).unwrap();
-                        mob.position = pos;
+                match option {
+                    None => {
+                        self.drag = Drag::Rubberband { start };
+                    },
+                    Some(id) => {
+                        if map.is_selected(map.selected_layer, id) {
+                            let start_state = self.history.capture(map, map.selected_layer, &map.selection);
+                            self.drag = Drag::Objects { start_state, last_position: start };
+                        }
                     }
                 }
             }
+
+            if let Drag::Objects { last_position, .. } = &mut self.drag {
+                map.move_selected_objects(pos[0] - last_position[0], pos[1] - last_position[1]);
+                *last_position = pos;
+            }
+
+            if let Drag::Transition { index, last_position, .. } = &mut self.drag {
+                if let Some(transit) = map.transitions.get_mut(*index) {
+                    transit.from = [transit.from[0] + pos[0] - last_position[0], transit.from[1] + pos[1] - last_position[1]];
+                }
+                *last_position = pos;
+            }
         }
 
         false
@@ -287,8 +724,16 @@ impl UiController for MapEditor {
 
 
     fn draw(&mut self, target: &mut Frame, program: &Program,
-            ui: &mut UI, _world: &mut Self::Appdata) {
+            ui: &mut UI, world: &mut Self::Appdata) {
+        self.inspector.sync(ui, &world.map);
+
         ui.draw(target, program);
+        self.inspector.draw(ui, target, program);
+    }
+
+
+    fn has_keyboard_focus(&self) -> bool {
+        self.inspector.has_focus()
     }
 
 
@@ -297,40 +742,85 @@ impl UiController for MapEditor {
 
         let layer_id = world.map.selected_layer;
         let id = self.selected_tile_id;
-        let set = &world.layer_tileset[layer_id];
+        let set = &world.layer_tileset[world.map.layer_info[layer_id].tileset_id];
         let tile_opt = set.tiles_by_id.get(&id);
 
         if tile_opt.is_some() {
             let tile = tile_opt.unwrap();
-            let player_position = &world.map.player_position();
+            let camera = &world.camera;
 
             let mp = &ui.context.mouse_state.position;
             let window_center: Vector2<f64> = ui.window_center(); 
 
-            let pos = screen_to_world_pos(&ui, player_position, mp);
-            let tpos = calc_tile_position(&pos, tile.foot, 1.0, player_position, &window_center);            
+            let pos = screen_to_world_pos(ui, camera, mp);
+            let tpos = calc_tile_position(&pos, tile.foot, 1.0, camera, &window_center);            
             
             draw_texture(&ui.display, target, program,
                 BlendMode::Blend,
                 &tile.tex,
                 tpos[0],
                 tpos[1], 
-                1.0, 
-                1.0,
+                camera.zoom as f32, 
+                camera.zoom as f32,
                 &[1.0, 1.0, 1.0, 0.5]);
         }
 
+        self.draw_transitions(target, program, ui, world);
+
+        if self.brush_mode != BrushMode::Off {
+            let mp = &ui.context.mouse_state.position;
+            let radius = (self.brush.radius * world.camera.zoom) as f32;
+            let color = if self.brush_mode == BrushMode::Paint { [0.5, 1.0, 0.5, 0.8] } else { [1.0, 0.5, 0.4, 0.8] };
+
+            draw_circle(ui, target, program, [mp[0] as f32, mp[1] as f32], radius, &color);
+        }
+
+        if let Drag::Rubberband { start } = &self.drag {
+            let window_center: Vector2<f64> = ui.window_center(); 
+            let corner = calc_tile_position(start, [0.0, 0.0], 1.0, &world.camera, &window_center);
+            let mp = &ui.context.mouse_state.position;
+
+            draw_rubberband(ui, target, program, [corner[0], corner[1]], [mp[0] as f32, mp[1] as f32]);
+        }
+
         let font = &ui.context.font_14;
         
         font.draw(&ui.display, target, program, 10, 20, "Press F1 to see editor hotkeys", &[1.0, 1.0, 1.0, 1.0]);
-        font.draw(&ui.display, target, program, 10, 40, "Press g to enter game mode", &[1.0, 1.0, 1.0, 1.0]);
+        font.draw(&ui.display, target, program, 10, 40, "Press g to play from here, e returns to the map as it was", &[1.0, 1.0, 1.0, 1.0]);
 
         let layer_msg = 
             "Selected layer: ".to_string() + &(layer_id + 1).to_string() + 
-            "  Selected tile: " + &self.selected_tile_id.to_string();
+            " (" + &world.map.layer_info[layer_id].name + ")" +
+            "  Selected tile: " + &self.selected_tile_id.to_string() +
+            "  Selected objects: " + &world.map.selection.len().to_string() +
+            "  File: " + &world.map.filename +
+            if self.transition_mode { "  Editing transitions" } else { "" } +
+            &match self.brush_mode {
+                BrushMode::Off => String::new(),
+                BrushMode::Paint => format!("  Brush: paint, radius {}, {} tiles", self.brush.radius, self.brush.tiles.len()),
+                BrushMode::Erase => format!("  Brush: erase, radius {}", self.brush.radius),
+            };
 
         font.draw(&ui.display, target, program, 10, (ui.context.window_size[1] - 24) as i32, &layer_msg, &[1.0, 1.0, 1.0, 1.0]);
 
+        // layer flags, e.g. "3:hidden" or "2:locked"
+        let mut flags_msg = "Layers:".to_string();
+        for (layer, flags) in world.map.layer_flags.iter().enumerate() {
+            flags_msg += &("  ".to_string() + &(layer + 1).to_string());
+            if flags.hidden {
+                flags_msg += ":hidden";
+            }
+            if flags.locked {
+                flags_msg += ":locked";
+            }
+        }
+
+        font.draw(&ui.display, target, program, 10, (ui.context.window_size[1] - 48) as i32, &flags_msg, &[1.0, 1.0, 1.0, 1.0]);
+
+        if let Some(message) = &self.error_message {
+            font.draw(&ui.display, target, program, 10, (ui.context.window_size[1] - 72) as i32, message, &[1.0, 0.5, 0.4, 1.0]);
+        }
+
 
         if self.show_editor_keys {
             let color = [1.0, 1.0, 1.0, 1.0];
@@ -341,21 +831,47 @@ impl UiController for MapEditor {
 
             font.draw(&ui.display, target, program, left, top, "F1: Show/hide this list", &color);
             top += line_space;
-            font.draw(&ui.display, target, program, left, top, "Space: Open tile selector", &color);
+            font.draw(&ui.display, target, program, left, top, "Space: Open tile selector. Type to filter by name, right click a tile to mark it as favorite", &color);
             top += line_space;
             font.draw(&ui.display, target, program, left, top, "1,2,3,.. : Select map layer", &color);
             top += line_space;
+            font.draw(&ui.display, target, program, left, top, "h, k: Hide, lock the selected layer", &color);
+            top += line_space;
+            font.draw(&ui.display, target, program, left, top, "Middle drag, Ctrl+Arrows: Move the view, Ctrl+Wheel: Zoom, Home: Center on the player", &color);
+            top += line_space;
+            font.draw(&ui.display, target, program, left, top, "i: Show/hide the properties of the selected item. Enter applies a typed value", &color);
+            top += line_space;
+            font.draw(&ui.display, target, program, left, top, "p: Place a particle emitter on the cursor tile. Its settings can be changed in the properties", &color);
+            top += line_space;
             font.draw(&ui.display, target, program, left, top, "c: Open color selector for selected item", &color);
             top += line_space;
             font.draw(&ui.display, target, program, left, top, "a: Set blend mode on selected item to 'Addition'", &color);
             top += line_space;
             font.draw(&ui.display, target, program, left, top, "m: Set blend mode on selected item to 'Mix' (default)", &color);
             top += line_space;
-            font.draw(&ui.display, target, program, left, top, "Delete: Removes the selected item from the map", &color);
+            font.draw(&ui.display, target, program, left, top, "Shift+Click, drag on empty ground: Add to selection, select in rectangle", &color);
+            top += line_space;
+            font.draw(&ui.display, target, program, left, top, "Delete: Removes the selected items from the map", &color);
+            top += line_space;
+            font.draw(&ui.display, target, program, left, top, "Ctrl+Z, Ctrl+Y: Undo, redo the last change", &color);
+            top += line_space;
+            font.draw(&ui.display, target, program, left, top, "Ctrl+C, Ctrl+X, Ctrl+V: Copy, cut, paste at the mouse position", &color);
+            top += line_space;
+            font.draw(&ui.display, target, program, left, top, "Ctrl+D: Duplicate the selected items", &color);
+            top += line_space;
+            font.draw(&ui.display, target, program, left, top, "b: Switch the brush between paint, erase and off. Drag to use it, [ and ]: Change the radius", &color);
             top += line_space;
-            font.draw(&ui.display, target, program, left, top, "l: Load a saved map", &color);
+            font.draw(&ui.display, target, program, left, top, "    While painting, tiles picked in the tile selector are added to the brush. Shift+B: Clear them", &color);
             top += line_space;
-            font.draw(&ui.display, target, program, left, top, "s: Save the map", &color);
+            font.draw(&ui.display, target, program, left, top, "t: Toggle transition editing. Click to select, drag to move, wheel to resize", &color);
+            top += line_space;
+            font.draw(&ui.display, target, program, left, top, "    Insert: new transition, Enter: set destination, Delete: remove transition", &color);
+            top += line_space;
+            font.draw(&ui.display, target, program, left, top, "l: Open a map file", &color);
+            top += line_space;
+            font.draw(&ui.display, target, program, left, top, "n: Generate a new map from a random seed", &color);
+            top += line_space;
+            font.draw(&ui.display, target, program, left, top, "s: Save the map under a chosen name", &color);
             // top += line_space;
         }
     }
@@ -367,6 +883,17 @@ impl UiController for MapEditor {
         let speaker = &mut world.speaker;
 
         map.update(dt, rng, speaker);
+
+        match self.autosave.update(map, dt) {
+            Ok(Some(recovery_file)) => {
+                println!("Autosaved the map to maps/{}", recovery_file);
+            },
+            Ok(None) => {},
+            Err(error) => {
+                println!("Error: autosave failed: {}", error);
+                self.error_message = Some(format!("Autosave failed: {}", error));
+            }
+        }
     }
 }
 
@@ -377,107 +904,1112 @@ impl MapEditor {
         MapEditor {
             selected_tile_id: 0,
             show_editor_keys: false,
+            error_message: None,
+            history: EditHistory::new(load_editor_config().undo_depth),
+            press_position: None,
+            drag: Drag::None,
+            pan_position: None,
+            clipboard: Vec::new(),
+            file_dialog: None,
+            transition_mode: false,
+            selected_transition: None,
+            destination_dialog: None,
+            inspector: Inspector::new(),
+            tile_selector: None,
+            palette: TilePalette::load(),
+            brush_mode: BrushMode::Off,
+            brush: load_brush_config(),
+            autosave: Autosave::new(AUTOSAVE_INTERVAL),
+            recovery_dialog: None,
         }
     }
 
 
-    fn select_nearest_item(&self, ui: &UI, world: &mut GameWorld) -> bool {
-        let pos = screen_to_world_pos(ui, &world.map.player_position(), &ui.context.mouse_state.position);
-        let map = &mut world.map;
-        let option = map.find_nearest_object(map.selected_layer, &pos, 100.0, 0);
-
-        match option {
-            None => {
-                map.has_selection = false;
-                map.selected_item = 0;
-            },
-            Some(id) => {
-
-                // toggle
-                if map.has_selection && map.selected_item == id {
-                    // was already seelected, unselect
-                    map.has_selection = false;
-                    map.selected_item = 0;
-                }
-                else {
-                    map.has_selection = true;
-                    map.selected_item = id;
-                }
-
-                return true;
-            }
+    /**
+     * Asks whether to restore an autosaved map, if there is one which is newer
+     * than its map file. Called once at startup.
+     */
+    pub fn offer_recovery(&mut self, ui: &mut UI) {
+        if let Some((recovery_file, map_filename)) = find_recovery_file() {
+            println!("Found autosaved map maps/{}", recovery_file);
+
+            let dialog = RecoveryDialog { recovery_file, map_filename };
+            ui.root.head.clear();
+            ui.root.head.add_child(make_recovery_dialog(ui, &dialog));
+            self.recovery_dialog = Some(dialog);
         }
-
-        false
     }
 
 
-    pub fn make_tile_selector(&self, ui: &UI, tileset: &TileSet) -> UiComponent {
-        // let count = tileset.tiles_by_id.len();
-        // let rows = count / 8;
-        let size = &ui.context.window_size;
-        
-        let ww = size[0] as i32;
-        let wh = size[1] as i32;
-        let w = 800;
-        let h = 600;
-        let x_space = 134;
-        let y_space = 150;
-
-        let mut cont = ui.make_container(0, 0, w, h);
-
-        let mut x = 0;
-        let mut y = 0;
-
-        for i in 0..10000 {
+    fn handle_recovery_dialog_event(&mut self, ui: &mut UI, world: &mut GameWorld, id: usize) {
+        let dialog = self.recovery_dialog.as_ref().unwrap();
 
-            let id = tileset.tiles_order_to_id.get(&i);
-            match id {
-                None => {
+        match id {
+            RECOVERY_RESTORE_ID => {
+                match world.map.load(&dialog.recovery_file, &mut world.rng) {
+                    Ok(()) => {
+                        println!("Restored the autosaved map maps/{}", dialog.recovery_file);
 
-                },
-                Some(id) => {
-                    let tile = tileset.tiles_by_id.get(id).unwrap();
-                    let icon = ui.make_icon(x+10, y+10, x_space-20, y_space-20, 
-                                            tile, &tile.name, *id);
-                        
-                    cont.head.add_child(icon);
-        
-                    x += x_space;
-        
-                    if x >= w {
-                        x = 0;
-                        y += y_space;
+                        // the recovery file is kept until the map is saved
+                        world.map.filename = dialog.map_filename.to_string();
+                        world.reload_map_textures();
+                        world.camera = Camera::new(world.map.player_position());
+                        self.autosave.reset(&world.map);
+                        self.history.clear();
+                        self.selected_transition = None;
+                        self.error_message = None;
+                    },
+                    Err(error) => {
+                        println!("Error: {}", error);
+                        self.error_message = Some(format!("Cannot restore maps/{}: {}", dialog.recovery_file, error));
                     }
                 }
+            },
+            RECOVERY_DISCARD_ID => {
+                remove_recovery_file(&dialog.map_filename);
+            },
+            _ => {
+                return;
             }
         }
 
-        let scrolly = ui.make_scrollpane((ww - w)/2, (wh - h)/2, w, h, cont, 64, 64);
-        scrolly
+        self.recovery_dialog = None;
+        ui.root.head.clear();
     }
-}
 
 
-fn place_particle_generator(world: &mut GameWorld, pos: Vector2<f64>) {
-    let id = 212;
-    let map = &mut world.map;
-    let layer = MAP_OBJECT_LAYER;
-    let height = world.layer_tileset[layer].tiles_by_id.get(&id).unwrap().foot[1];
-    let mut mob = map.factory.create_mob(id, layer, pos, height, 1.0);
-    let mob_id = mob.uid;
+    /**
+     * Paints or erases objects inside the brush circle.
+     *
+     * @return the changes, to be recorded in the history when the stroke ends
+     */
+    fn brush_dab(&self, world: &mut GameWorld, position: &Vector2<f64>) -> Vec<EditChange> {
+        let map = &mut world.map;
+        let layer = map.selected_layer;
+        let mut changes = Vec::new();
+
+        if self.brush_mode == BrushMode::Erase {
+            for uid in objects_to_erase(map, layer, position, self.brush.radius) {
+                let stash = map.remove_object(layer, uid);
+                changes.push(EditChange::Remove { layer, uid, stash });
+            }
+            map.prune_selection();
+        }
+        else {
+            // without chosen brush tiles, the brush scatters the tile on the cursor
+            let tiles = if self.brush.tiles.is_empty() { vec![self.selected_tile_id] } else { self.brush.tiles.clone() };
+            let tileset_id = map.layer_info[layer].tileset_id;
+            let set = &world.layer_tileset[tileset_id];
+
+            for item in scatter(map, layer, position, &self.brush, &tiles, &mut world.rng) {
+                if let Some(tile) = set.tiles_by_id.get(&item.tile_id) {
+                    let mut mob = map.factory.create_mob(item.tile_id, tileset_id, item.position, tile.foot[1], item.scale);
+                    mob.visual.color = item.color;
+                    changes.push(EditChange::Insert { layer, uid: mob.uid, stash: None });
+                    map.add_object(layer, mob);
+                }
+            }
+        }
+
+        changes
+    }
+
+
+    /**
+     * Undo, redo and the clipboard keys, while Ctrl is held.
+     *
+     * @return true if the key was one of them
+     */
+    fn handle_ctrl_key(&mut self, ui: &UI, world: &mut GameWorld, key: &str) -> bool {
+        match key {
+            "z" => {
+                if !self.history.undo(&mut world.map) {
+                    println!("Nothing to undo");
+                }
+            },
+            "y" => {
+                if !self.history.redo(&mut world.map) {
+                    println!("Nothing to redo");
+                }
+            },
+            "c" => {
+                self.clipboard = copy_selection(&world.map);
+            },
+            // cut, paste and duplicate change the selected layer
+            "x" | "v" | "d" if self.refuse_locked(&world.map, world.map.selected_layer) => {},
+            "x" => {
+                self.clipboard = copy_selection(&world.map);
+                let map = &mut world.map;
+                let selection = std::mem::take(&mut map.selection);
+                self.history.remove_objects(map, map.selected_layer, &selection);
+            },
+            "v" => {
+                let pos = screen_to_world_pos(ui, &world.camera, 
+                                              &ui.context.mouse_state.position);
+                let items = std::mem::take(&mut self.clipboard);
+                self.paste(&mut world.map, &items, pos);
+                self.clipboard = items;
+            },
+            "d" => {
+                let items = copy_selection(&world.map);
+                let center = selection_center(&world.map);
+                self.paste(&mut world.map, &items, [center[0] + DUPLICATE_OFFSET[0], center[1] + DUPLICATE_OFFSET[1]]);
+            },
+            _ => {
+                return false;
+            }
+        }
+
+        world.map.prune_selection();
+
+        // undo and redo may have removed the selected transition
+        self.selected_transition = self.selected_transition.filter(|index| *index < world.map.transitions.len());
+
+        true
+    }
+
+
+    /**
+     * Ctrl+arrow keys move the camera, Home centers it on the player.
+     *
+     * @return true if the key was handled
+     */
+    fn handle_camera_key(&mut self, ui: &UI, world: &mut GameWorld, button: &Button) -> bool {
+        let camera = &mut world.camera;
+
+        if *button == Button::Keyboard(Key::Named(NamedKey::Home)) {
+            *camera = Camera::new(world.map.player_position());
+            return true;
+        }
+
+        if !ui.context.keyboard_state.ctrl_pressed {
+            return false;
+        }
+
+        match button {
+            Button::Keyboard(Key::Named(NamedKey::ArrowLeft)) => camera.pan(-CAMERA_PAN_STEP, 0.0),
+            Button::Keyboard(Key::Named(NamedKey::ArrowRight)) => camera.pan(CAMERA_PAN_STEP, 0.0),
+            Button::Keyboard(Key::Named(NamedKey::ArrowUp)) => camera.pan(0.0, -CAMERA_PAN_STEP),
+            Button::Keyboard(Key::Named(NamedKey::ArrowDown)) => camera.pan(0.0, CAMERA_PAN_STEP),
+            _ => return false,
+        }
+
+        true
+    }
+
+
+    /**
+     * Enter in a field of the inspector applies the typed value to the inspected object,
+     * Escape leaves the field without changes.
+     */
+    fn handle_inspector_event(&mut self, event: &ButtonEvent, world: &mut GameWorld) {
+        let option = self.inspector.handle_button_event(event);
+
+        if event.args.state != ButtonState::Release {
+            return;
+        }
+
+        if event.args.button == Button::Keyboard(Key::Named(NamedKey::Escape)) {
+            self.inspector.release_focus();
+            return;
+        }
+
+        let option = option.filter(|(id, _)| {
+            // a click into a text field only focuses it, Enter applies the value
+            event.args.button == Button::Keyboard(Key::Named(NamedKey::Enter)) ||
+            (event.args.button == Button::Mouse(MouseButton::Left) && is_button(*id))
+        });
+
+        if let (Some((id, text)), Some((layer, uid))) = (option, self.inspector.target()) {
+            let map = &mut world.map;
+            let state = match map.layers[layer].get(&uid) {
+                Some(mob) => ObjectState::of(mob),
+                None => return,
+            };
+
+            if self.refuse_locked(map, layer) {
+                return;
+            }
+
+            let tilesets = &world.layer_tileset;
+            let tile_exists = |set: usize, tile: usize| tilesets.get(set).is_some_and(|set| set.tiles_by_id.contains_key(&tile));
+
+            match edited_state(&state, id, &text, tile_exists) {
+                Ok(new_state) => {
+                    self.history.modify_objects(map, layer, &[uid], |map| new_state.apply(map, layer, uid));
+                    self.inspector.release_focus();
+                    self.error_message = None;
+                },
+                Err(message) => {
+                    self.error_message = Some(message);
+                }
+            }
+        }
+    }
+
+
+    /**
+     * Applies the function to the map, so that the changes of the selected objects can be undone.
+     */
+    fn modify_selected<F>(&mut self, map: &mut Map, func: F)
+        where F: FnOnce(&mut Map) {
+        if map.has_selection() && !self.refuse_locked(map, map.selected_layer) {
+            let layer = map.selected_layer;
+            let selection = map.selection.clone();
+            self.history.modify_objects(map, layer, &selection, func);
+        }
+    }
+
+
+    /**
+     * Selects the object under the mouse. If add is true, the object is added to
+     * the selection, or removed if it was selected already.
+     */
+    fn select_nearest_item(&self, ui: &UI, world: &mut GameWorld, add: bool) -> bool {
+        let pos = screen_to_world_pos(ui, &world.camera, &ui.context.mouse_state.position);
+        let map = &mut world.map;
+        let option = map.find_nearest_object(map.selected_layer, &pos, 100.0, 0);
+
+        match option {
+            None => {
+                if !add {
+                    map.selection.clear();
+                }
+            },
+            Some(id) => {
+
+                if add {
+                    map.toggle_selection(id);
+                }
+                else if map.selection == [id] {
+                    // was already selected, unselect
+                    map.selection.clear();
+                }
+                else {
+                    map.selection = vec![id];
+                }
+
+                return true;
+            }
+        }
+
+        false
+    }
+
+
+    fn open_file_dialog(&mut self, ui: &mut UI, world: &GameWorld, mode: FileDialogMode) {
+        let files =
+            match list_map_files() {
+                Ok(files) => files,
+                Err(error) => {
+                    println!("Error: cannot list the maps directory: {}", error);
+                    self.error_message = Some(error.to_string());
+                    Vec::new()
+                }
+            };
+
+        let filename = if world.map.filename.is_empty() { "new.map".to_string() } else { world.map.filename.to_string() };
+
+        self.tile_selector = None;
+        self.file_dialog = Some(FileDialog { mode, files, filename, confirm_overwrite: false });
+        self.show_file_dialog(ui);
+    }
+
+
+    fn show_file_dialog(&self, ui: &mut UI) {
+        ui.root.head.clear();
+
+        if let Some(dialog) = &self.file_dialog {
+            let cont = make_file_dialog(ui, dialog);
+            ui.root.head.add_child(cont);
+        }
+    }
+
+
+    fn close_file_dialog(&mut self, ui: &mut UI) {
+        self.file_dialog = None;
+        ui.root.head.clear();
+    }
+
+
+    fn handle_file_dialog_event(&mut self, ui: &mut UI, world: &mut GameWorld, id: usize, text: String, button: &Button) {
+        let dialog = self.file_dialog.as_mut().unwrap();
+
+        match id {
+            FILENAME_FIELD_ID => {
+                dialog.filename = text;
+
+                if *button == Button::Keyboard(Key::Named(NamedKey::Enter)) {
+                    self.submit_file_dialog(ui, world);
+                }
+
+                if *button == Button::Keyboard(Key::Named(NamedKey::Escape)) {
+                    self.close_file_dialog(ui);
+                }
+            },
+            FILE_OK_ID => {
+                self.submit_file_dialog(ui, world);
+            },
+            FILE_CANCEL_ID => {
+                self.close_file_dialog(ui);
+            },
+            OVERWRITE_YES_ID => {
+                let filename = dialog.filename.to_string();
+                self.save_map(ui, world, &filename);
+            },
+            OVERWRITE_NO_ID => {
+                dialog.confirm_overwrite = false;
+                self.show_file_dialog(ui);
+            },
+            _ => {
+                // a file from the list was chosen
+                if let Some(filename) = id.checked_sub(FILE_LIST_ID).and_then(|i| dialog.files.get(i)) {
+                    dialog.filename = filename.to_string();
+                    self.show_file_dialog(ui);
+                }
+            }
+        }
+    }
+
+
+    fn submit_file_dialog(&mut self, ui: &mut UI, world: &mut GameWorld) {
+        let dialog = self.file_dialog.as_mut().unwrap();
+        let mut filename = dialog.filename.trim().to_string();
+
+        // only plain file names, all maps are in the maps directory
+        if filename.is_empty() || filename.contains('/') || filename.contains('\\') {
+            self.error_message = Some(format!("Invalid map file name '{}'", filename));
+            return;
+        }
+
+        if !filename.ends_with(".map") {
+            filename += ".map";
+        }
+
+        dialog.filename = filename.to_string();
+
+        if dialog.mode == FileDialogMode::Open {
+            match world.map.load(&filename, &mut world.rng) {
+                Ok(()) => {
+                    world.reload_map_textures();
+                    world.camera = Camera::new(world.map.player_position());
+                    self.autosave.reset(&world.map);
+                    self.history.clear();
+                    self.selected_transition = None;
+                    self.error_message = None;
+                    self.close_file_dialog(ui);
+                },
+                Err(error) => {
+                    println!("Error: {}", error);
+                    self.error_message = Some(error.to_string());
+                }
+            }
+        }
+        else if dialog.files.contains(&filename) && filename != world.map.filename {
+            dialog.confirm_overwrite = true;
+            self.show_file_dialog(ui);
+        }
+        else {
+            self.save_map(ui, world, &filename);
+        }
+    }
+
+
+    fn save_map(&mut self, ui: &mut UI, world: &mut GameWorld, filename: &str) {
+        match world.map.save(filename) {
+            Ok(()) => {
+                println!("Saved map to maps/{}", filename);
+
+                // the autosaved changes are in the map file now
+                remove_recovery_file(&world.map.filename);
+                remove_recovery_file(filename);
+                self.autosave.reset(&world.map);
+
+                world.map.filename = filename.to_string();
+                self.error_message = None;
+                self.close_file_dialog(ui);
+            },
+            Err(error) => {
+                println!("Error: {}", error);
+                self.error_message = Some(format!("Cannot save maps/{}: {}", filename, error));
+            }
+        }
+    }
+
+
+    /**
+     * @return true if the layer is locked. Tells the user why nothing happens.
+     */
+    fn refuse_locked(&mut self, map: &Map, layer: usize) -> bool {
+        if map.layer_flags[layer].locked {
+            self.error_message = Some(format!("Layer {} ({}) is locked", layer + 1, map.layer_info[layer].name));
+            true
+        }
+        else {
+            false
+        }
+    }
+
+
+    /**
+     * Handles the keys of the transition mode
+     *
+     * @return true if the key was used
+     */
+    fn handle_transition_key(&mut self, ui: &mut UI, world: &mut GameWorld, button: &Button, mouse_pos: Vector2<f64>) -> bool {
+        let map = &mut world.map;
+
+        if *button == Button::Keyboard(Key::Named(NamedKey::Insert)) {
+            // the new transition leads to this map, until a destination is set
+            let to_map = if map.filename.is_empty() { "start.map".to_string() } else { map.filename.to_string() };
+
+            let transit = MapTransition {
+                from: mouse_pos,
+                rad: TRANSITION_RADIUS,
+                to_map,
+                to_position: mouse_pos,
+                facing: 0,
+            };
+            self.history.modify_transitions(map, |transitions| transitions.push(transit));
+
+            let index = map.transitions.len() - 1;
+            self.selected_transition = Some(index);
+            self.open_destination_dialog(ui, map, index);
+            return true;
+        }
+
+        let selected = self.selected_transition.filter(|index| *index < map.transitions.len());
+
+        if let Some(index) = selected {
+            if *button == Button::Keyboard(Key::Named(NamedKey::Delete)) {
+                self.history.modify_transitions(map, |transitions| { transitions.remove(index); });
+                self.selected_transition = None;
+                return true;
+            }
+
+            if *button == Button::Keyboard(Key::Named(NamedKey::Enter)) {
+                self.open_destination_dialog(ui, map, index);
+                return true;
+            }
+
+            // arrow keys move the selected transition
+            let step = if ui.context.keyboard_state.shift_pressed {8.0} else {1.0};
+            let moves = [(NamedKey::ArrowRight, step, 0.0), (NamedKey::ArrowLeft, -step, 0.0),
+                         (NamedKey::ArrowUp, 0.0, -step), (NamedKey::ArrowDown, 0.0, step)];
+
+            for (key, dx, dy) in moves {
+                if *button == Button::Keyboard(Key::Named(key)) {
+                    self.history.modify_transitions(map, |transitions| {
+                        let transit = &mut transitions[index];
+                        transit.from = [transit.from[0] + dx, transit.from[1] + dy];
+                    });
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+
+    fn open_destination_dialog(&mut self, ui: &mut UI, map: &Map, index: usize) {
+        let files = list_map_files().unwrap_or_else(|error| {
+            println!("Error: cannot list the maps directory: {}", error);
+            Vec::new()
+        });
+
+        let transit = &map.transitions[index];
+
+        self.tile_selector = None;
+        self.destination_dialog = Some(DestinationDialog {
+            transition: index,
+            files,
+            to_map: transit.to_map.to_string(),
+            x: transit.to_position[0].to_string(),
+            y: transit.to_position[1].to_string(),
+            facing: transit.facing.to_string(),
+        });
+
+        self.show_destination_dialog(ui);
+    }
+
+
+    fn show_destination_dialog(&self, ui: &mut UI) {
+        ui.root.head.clear();
+
+        if let Some(dialog) = &self.destination_dialog {
+            let cont = make_destination_dialog(ui, dialog);
+            ui.root.head.add_child(cont);
+        }
+    }
+
+
+    fn close_destination_dialog(&mut self, ui: &mut UI) {
+        self.destination_dialog = None;
+        ui.root.head.clear();
+    }
+
 
-    let particles = &mut mob.visual.particles;
+    fn handle_destination_dialog_event(&mut self, ui: &mut UI, world: &mut GameWorld, id: usize, text: String, button: &Button) {
+        let dialog = self.destination_dialog.as_mut().unwrap();
 
-    let ids = [44, 45, 46, 47, 48, 49, 50, 51];
+        match id {
+            DESTINATION_MAP_FIELD_ID => dialog.to_map = text,
+            DESTINATION_X_FIELD_ID => dialog.x = text,
+            DESTINATION_Y_FIELD_ID => dialog.y = text,
+            DESTINATION_FACING_FIELD_ID => dialog.facing = text,
+            DESTINATION_OK_ID => {
+                self.submit_destination_dialog(ui, world);
+                return;
+            },
+            DESTINATION_CANCEL_ID => {
+                self.close_destination_dialog(ui);
+                return;
+            },
+            _ => {
+                // a map from the list was chosen
+                if let Some(filename) = id.checked_sub(DESTINATION_LIST_ID).and_then(|i| dialog.files.get(i)) {
+                    dialog.to_map = filename.to_string();
+                    self.show_destination_dialog(ui);
+                }
+                return;
+            }
+        }
+
+        if *button == Button::Keyboard(Key::Named(NamedKey::Enter)) {
+            self.submit_destination_dialog(ui, world);
+        }
+
+        if *button == Button::Keyboard(Key::Named(NamedKey::Escape)) {
+            self.close_destination_dialog(ui);
+        }
+    }
+
+
+    fn submit_destination_dialog(&mut self, ui: &mut UI, world: &mut GameWorld) {
+        let dialog = self.destination_dialog.as_ref().unwrap();
+
+        let to_map = dialog.to_map.trim();
+        let x = dialog.x.trim().parse::<f64>();
+        let y = dialog.y.trim().parse::<f64>();
+        let facing = dialog.facing.trim().parse::<usize>();
+
+        match (x, y, facing) {
+            (Ok(x), Ok(y), Ok(facing)) if !to_map.is_empty() => {
+                self.history.modify_transitions(&mut world.map, |transitions| {
+                    if let Some(transit) = transitions.get_mut(dialog.transition) {
+                        transit.to_map = to_map.to_string();
+                        transit.to_position = [x, y];
+                        transit.facing = facing;
+                    }
+                });
+
+                self.error_message = None;
+                self.close_destination_dialog(ui);
+            },
+            _ => {
+                self.error_message = Some("The destination needs a map name, x and y coordinates and a facing".to_string());
+            }
+        }
+    }
+
+
+    fn draw_transitions(&self, target: &mut Frame, program: &Program, ui: &UI, world: &GameWorld) {
+        let camera = &world.camera;
+        let window_center: Vector2<f64> = ui.window_center(); 
+        let font = &ui.context.font_14;
+
+        // transitions are only shown faintly if they can't be edited
+        let alpha = if self.transition_mode { 0.9 } else { 0.35 };
+
+        for (index, transit) in world.map.transitions.iter().enumerate() {
+            let center = calc_tile_position(&transit.from, [0.0, 0.0], 1.0, camera, &window_center);
+            let color = 
+                if self.selected_transition == Some(index) {
+                    [1.0, 0.9, 0.3, alpha]
+                }
+                else {
+                    [0.4, 0.8, 1.0, alpha]
+                };
+
+            draw_circle(ui, target, program, center, (transit.rad * camera.zoom) as f32, &color);
+
+            let label = "-> ".to_string() + &transit.to_map + 
+                " (" + &transit.to_position[0].to_string() + ", " + &transit.to_position[1].to_string() + ")";
+            let label_width = font.calc_string_width(&label) as i32;
+
+            font.draw(&ui.display, target, program, center[0] as i32 - label_width / 2, center[1] as i32 - font.lineheight / 2, &label, &color);
+        }
+    }
+
+
+    /**
+     * Adds copies of the items to the selected layer, centered at the given position.
+     * The new objects become the selection.
+     */
+    fn paste(&mut self, map: &mut Map, items: &[ClipboardItem], center: Vector2<f64>) {
+        let layer = map.selected_layer;
+        let mut uids = Vec::new();
+
+        // tile ids only mean something in their own tileset
+        let info = &map.layer_info[layer];
+        if items.iter().any(|item| item.visual.tileset_id != info.tileset_id) {
+            self.error_message = Some(format!("Cannot paste into layer {} ({}), it uses another tileset", layer + 1, info.name));
+            return;
+        }
+
+        for item in items {
+            let position = [center[0] + item.offset[0], center[1] + item.offset[1]];
+            let mut mob = map.factory.create_mob(item.visual.base_image_id, item.visual.tileset_id, position, item.visual.height, item.visual.scale);
+            mob.visual = item.visual.duplicate();
+            mob.footprint = item.footprint;
+
+            uids.push(mob.uid);
+            map.add_object(layer, mob);
+        }
+
+        self.history.push_inserts(layer, &uids);
+
+        if !uids.is_empty() {
+            map.selection = uids;
+        }
+    }
+
+
+    fn show_tile_selector(&self, ui: &mut UI, world: &GameWorld) {
+        ui.root.head.clear();
+
+        if let Some(selector) = &self.tile_selector {
+            let cont = self.make_tile_selector(ui, &world.layer_tileset[selector.tileset_id], selector);
+            ui.root.head.add_child(cont);
+        }
+    }
+
+
+    /**
+     * Typing changes the filter. A left click on a tile picks it, a right click
+     * adds it to the favorites or removes it from them.
+     */
+    fn handle_tile_selector_event(&mut self, ui: &mut UI, world: &mut GameWorld, id: usize, text: String, button: &Button) {
+        let selector = self.tile_selector.as_mut().unwrap();
+
+        if id == TILE_FILTER_FIELD_ID {
+            if *button == Button::Keyboard(Key::Named(NamedKey::Escape)) {
+                self.tile_selector = None;
+                ui.root.head.clear();
+            }
+            else if text != selector.filter {
+                selector.filter = text;
+                self.show_tile_selector(ui, world);
+            }
+            return;
+        }
+
+        // only the tiles of the selector are valid choices
+        if !world.layer_tileset[selector.tileset_id].tiles_by_id.contains_key(&id) {
+            return;
+        }
+
+        if *button == Button::Mouse(MouseButton::Right) {
+            self.palette.toggle_favorite(selector.tileset_id, id);
+            self.palette.save();
+            self.show_tile_selector(ui, world);
+            return;
+        }
+
+        println!("Selected tile id={}", id);
+
+        world.speaker.play(Sound::Click, 0.5);
+        self.palette.use_tile(selector.tileset_id, id);
+        self.palette.save();
+
+        // while painting, several tiles can be picked for the brush
+        if self.brush_mode == BrushMode::Paint {
+            if self.brush.tiles.contains(&id) {
+                self.brush.tiles.retain(|tile_id| *tile_id != id);
+            }
+            else {
+                self.brush.tiles.push(id);
+            }
+            self.show_tile_selector(ui, world);
+            return;
+        }
+
+        self.selected_tile_id = id;
+        self.tile_selector = None;
+        ui.root.head.clear();
+    }
+
+
+    fn make_tile_selector(&self, ui: &UI, tileset: &TileSet, selector: &TileSelector) -> UiComponent {
+        let size = &ui.context.window_size;
+        
+        let ww = size[0] as i32;
+        let wh = size[1] as i32;
+        let w = 800;
+        let h = 600;
+        let x_space = 134;
+        let y_space = 150;
+        let section_space = 28;
+
+        let matches = |id: &usize| {
+            tileset.tiles_by_id.get(id).is_some_and(|tile| matches_filter(&tile.name, &selector.filter))
+        };
+
+        let favorites: Vec<usize> = self.palette.favorites(selector.tileset_id).iter().copied().filter(matches).collect();
+        let recent: Vec<usize> = self.palette.recent(selector.tileset_id).iter().copied().filter(matches).collect();
+
+        let mut order: Vec<&usize> = tileset.tiles_order_to_id.keys().collect();
+        order.sort();
+
+        let all: Vec<usize> = order.iter()
+            .map(|i| tileset.tiles_order_to_id[i])
+            .filter(matches)
+            .collect();
+
+        let all_title =
+            if selector.filter.is_empty() { "All tiles".to_string() } else { "Tiles matching '".to_string() + &selector.filter + "'" };
+
+        let sections = [
+            ("Favorites (right click a tile to add or remove it)".to_string(), favorites),
+            ("Recently used".to_string(), recent),
+            (all_title, all),
+        ];
+
+        let per_row = (w / x_space) as usize;
+        let content_height = sections.iter()
+            .filter(|(_, ids)| !ids.is_empty())
+            .map(|(_, ids)| section_space + ids.len().div_ceil(per_row) as i32 * y_space)
+            .sum::<i32>();
+
+        let mut cont = ui.make_container(0, 0, w, std::cmp::max(content_height, 1));
+        let mut y = 0;
+
+        for (title, ids) in &sections {
+            if ids.is_empty() {
+                continue;
+            }
+
+            cont.head.add_child(ui.make_label(10, y + 4, w - 20, 20, title));
+            y += section_space;
+
+            for (i, id) in ids.iter().enumerate() {
+                let tile = tileset.tiles_by_id.get(id).unwrap();
+                let x = (i % per_row) as i32 * x_space;
+                let row_y = y + (i / per_row) as i32 * y_space;
+
+                // tiles of the brush are marked while painting
+                let label = 
+                    if self.brush_mode == BrushMode::Paint && self.brush.tiles.contains(id) { "+ ".to_string() + &tile.name } else { tile.name.to_string() };
+                let icon = ui.make_icon(x+10, row_y+10, x_space-20, y_space-20, 
+                                        tile, &label, *id);
+                cont.head.add_child(icon);
+            }
 
-    for id in ids {
-        particles.spawn_ids.push(id+8);
+            y += ids.len().div_ceil(per_row) as i32 * y_space;
+        }
+
+        let mut frame = ui.make_container((ww - w)/2, (wh - h)/2 - 20, w, h + 40);
+        frame.head.add_child(ui.make_label(10, 8, 60, 24, "Filter:"));
+        frame.head.add_child(ui.make_text_field(70, 6, w - 80, 28, &selector.filter, TILE_FILTER_FIELD_ID));
+        frame.head.add_child(ui.make_scrollpane(0, 40, w, h, cont, 64, 64));
+
+        frame
+    }
+}
+
+
+fn make_file_dialog(ui: &UI, dialog: &FileDialog) -> UiComponent {
+    let size = &ui.context.window_size;
+    let ww = size[0] as i32;
+    let wh = size[1] as i32;
+
+    if dialog.confirm_overwrite {
+        let w = 440;
+        let h = 110;
+        let mut cont = ui.make_container((ww - w) / 2, (wh - h) / 2, w, h);
+
+        let question = "maps/".to_string() + &dialog.filename + " exists. Overwrite it?";
+        cont.head.add_child(ui.make_label(20, 16, w - 40, 24, &question));
+        cont.head.add_child(ui.make_button(w - 240, 64, 100, 28, "Overwrite", OVERWRITE_YES_ID));
+        cont.head.add_child(ui.make_button(w - 120, 64, 100, 28, "Cancel", OVERWRITE_NO_ID));
+
+        return cont;
+    }
+
+    let w = 500;
+    let h = 440;
+    let line_space = 28;
+    let mut cont = ui.make_container((ww - w) / 2, (wh - h) / 2, w, h);
+
+    let title = if dialog.mode == FileDialogMode::Open { "Open map" } else { "Save map as" };
+    cont.head.add_child(ui.make_label(20, 10, w - 40, 24, title));
+
+    let list_height = std::cmp::max(dialog.files.len() as i32 * line_space, 1);
+    let mut list = ui.make_container(0, 0, w - 40, list_height);
+
+    for (i, file) in dialog.files.iter().enumerate() {
+        list.head.add_child(ui.make_button(0, i as i32 * line_space, w - 40, line_space - 4, file, FILE_LIST_ID + i));
+    }
+
+    cont.head.add_child(ui.make_scrollpane(20, 44, w - 40, 280, list, 0, line_space));
+    cont.head.add_child(ui.make_text_field(20, 340, w - 40, 28, &dialog.filename, FILENAME_FIELD_ID));
+
+    let ok_label = if dialog.mode == FileDialogMode::Open { "Open" } else { "Save" };
+    cont.head.add_child(ui.make_button(w - 240, 390, 100, 28, ok_label, FILE_OK_ID));
+    cont.head.add_child(ui.make_button(w - 120, 390, 100, 28, "Cancel", FILE_CANCEL_ID));
+
+    cont
+}
+
+
+fn make_recovery_dialog(ui: &UI, dialog: &RecoveryDialog) -> UiComponent {
+    let size = &ui.context.window_size;
+    let ww = size[0] as i32;
+    let wh = size[1] as i32;
+
+    let w = 500;
+    let h = 140;
+    let mut cont = ui.make_container((ww - w) / 2, (wh - h) / 2, w, h);
+
+    let map_name = if dialog.map_filename.is_empty() { "an unsaved map" } else { &dialog.map_filename };
+    let text = "There are autosaved changes of ".to_string() + map_name + ".";
+    cont.head.add_child(ui.make_label(20, 16, w - 40, 24, &text));
+    cont.head.add_child(ui.make_label(20, 44, w - 40, 24, "Restore them?"));
+    cont.head.add_child(ui.make_button(w - 240, 94, 100, 28, "Restore", RECOVERY_RESTORE_ID));
+    cont.head.add_child(ui.make_button(w - 120, 94, 100, 28, "Discard", RECOVERY_DISCARD_ID));
+
+    cont
+}
+
+
+fn make_destination_dialog(ui: &UI, dialog: &DestinationDialog) -> UiComponent {
+    let size = &ui.context.window_size;
+    let ww = size[0] as i32;
+    let wh = size[1] as i32;
+
+    let w = 500;
+    let h = 480;
+    let line_space = 28;
+    let mut cont = ui.make_container((ww - w) / 2, (wh - h) / 2, w, h);
+
+    cont.head.add_child(ui.make_label(20, 10, w - 40, 24, "Destination of the transition"));
+
+    let list_height = std::cmp::max(dialog.files.len() as i32 * line_space, 1);
+    let mut list = ui.make_container(0, 0, w - 40, list_height);
+
+    for (i, file) in dialog.files.iter().enumerate() {
+        list.head.add_child(ui.make_button(0, i as i32 * line_space, w - 40, line_space - 4, file, DESTINATION_LIST_ID + i));
+    }
+
+    cont.head.add_child(ui.make_scrollpane(20, 44, w - 40, 200, list, 0, line_space));
+
+    let fields = [
+        ("Map", &dialog.to_map, DESTINATION_MAP_FIELD_ID),
+        ("X", &dialog.x, DESTINATION_X_FIELD_ID),
+        ("Y", &dialog.y, DESTINATION_Y_FIELD_ID),
+        ("Facing", &dialog.facing, DESTINATION_FACING_FIELD_ID),
+    ];
+
+    let mut y = 260;
+
+    for (label, text, id) in fields {
+        let mut field = ui.make_text_field(110, y, w - 130, 28, text, id);
+
+        // the map name has the focus first
+        field.head.set_focus(id == DESTINATION_MAP_FIELD_ID);
+
+        cont.head.add_child(ui.make_label(20, y, 80, 28, label));
+        cont.head.add_child(field);
+        y += 40;
+    }
+
+    cont.head.add_child(ui.make_button(w - 240, 430, 100, 28, "OK", DESTINATION_OK_ID));
+    cont.head.add_child(ui.make_button(w - 120, 430, 100, 28, "Cancel", DESTINATION_CANCEL_ID));
+
+    cont
+}
+
+
+/**
+ * @return the average position of the selected objects
+ */
+fn selection_center(map: &Map) -> Vector2<f64> {
+    let layer = &map.layers[map.selected_layer];
+    let mut sum = [0.0, 0.0];
+    let mut count = 0;
+
+    for uid in &map.selection {
+        if let Some(mob) = layer.get(uid) {
+            sum = [sum[0] + mob.position[0], sum[1] + mob.position[1]];
+            count += 1;
+        }
     }
 
-    particles.spawn_chance = 30.0;
-    particles.spawn_tile_set = MAP_CLOUD_LAYER;
+    if count == 0 {
+        sum
+    }
+    else {
+        [sum[0] / count as f64, sum[1] / count as f64]
+    }
+}
+
+
+/**
+ * Copies the selected map decorations. Creatures and the player can't be copied.
+ */
+fn copy_selection(map: &Map) -> Vec<ClipboardItem> {
+    let layer = &map.layers[map.selected_layer];
+    let center = selection_center(map);
+
+    map.selection.iter()
+        .filter_map(|uid| layer.get(uid))
+        .filter(|mob| mob.mob_type == MobType::MapObject)
+        .map(|mob| ClipboardItem {
+            offset: [mob.position[0] - center[0], mob.position[1] - center[1]],
+            visual: mob.visual.duplicate(),
+            footprint: mob.footprint,
+        })
+        .collect()
+}
+
+
+/**
+ * Draws a dotted circle of world coordinates. World y coordinates are
+ * twice the screen coordinates, so the circle becomes an ellipse.
+ */
+fn draw_circle(ui: &UI, target: &mut Frame, program: &Program, center: [f32; 2], radius: f32, color: &[f32; 4]) {
+    let rx = radius;
+    let ry = radius * 0.5;
+    let steps = 64;
+
+    for i in 0..steps {
+        let angle = i as f32 * std::f32::consts::TAU / steps as f32;
+        draw_texture(&ui.display, target, program,
+            BlendMode::Blend,
+            &ui.context.tex_white,
+            center[0] + rx * angle.cos() - 1.0,
+            center[1] + ry * angle.sin() - 1.0,
+            3.0 / 16.0,
+            3.0 / 16.0,
+            color);
+    }
+}
+
+
+/**
+ * General settings of the map editor. Missing entries in an editor
+ * config file are taken from the default config.
+ */
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct EditorConfig {
+    // number of actions which can be undone
+    pub undo_depth: usize,
+}
+
+
+impl Default for EditorConfig {
+    fn default() -> Self {
+        EditorConfig {
+            undo_depth: UNDO_DEPTH,
+        }
+    }
+}
+
+
+impl EditorConfig {
+
+    /**
+     * Reads an editor config file from the maps directory
+     */
+    pub fn load(filename: &str) -> Result<EditorConfig, String> {
+        let path = std::path::Path::new("maps").join(filename);
+        let content = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+
+        ron::from_str(&content).map_err(|error| error.to_string())
+    }
+}
+
+
+/**
+ * An editor.ron in the maps directory overrides the default editor settings.
+ */
+fn load_editor_config() -> EditorConfig {
+    if std::path::Path::new("maps/editor.ron").exists() {
+        EditorConfig::load("editor.ron").unwrap_or_else(|error| {
+            println!("Error in editor.ron: {}", error);
+            EditorConfig::default()
+        })
+    }
+    else {
+        EditorConfig::default()
+    }
+}
+
+
+/**
+ * A brush.ron in the maps directory overrides the default brush settings.
+ */
+fn load_brush_config() -> BrushConfig {
+    if std::path::Path::new("maps/brush.ron").exists() {
+        BrushConfig::load("brush.ron").unwrap_or_else(|error| {
+            println!("Error in brush.ron: {}", error);
+            BrushConfig::default()
+        })
+    }
+    else {
+        BrushConfig::default()
+    }
+}
+
+
+fn draw_rubberband(ui: &UI, target: &mut Frame, program: &Program, corner_1: [f32; 2], corner_2: [f32; 2]) {
+    let x = corner_1[0].min(corner_2[0]);
+    let y = corner_1[1].min(corner_2[1]);
+    let w = (corner_1[0] - corner_2[0]).abs();
+    let h = (corner_1[1] - corner_2[1]).abs();
+    let color = [0.6, 0.8, 1.0, 0.8];
+
+    // the white texture is 16x16 pixels, the edges are drawn 1 pixel wide
+    let edges = [(x, y, w, 1.0), (x, y + h, w, 1.0), (x, y, 1.0, h), (x + w, y, 1.0, h)];
+
+    for (ex, ey, ew, eh) in edges {
+        draw_texture(&ui.display, target, program,
+            BlendMode::Blend,
+            &ui.context.tex_white,
+            ex,
+            ey,
+            ew / 16.0,
+            eh / 16.0,
+            &color);
+    }
+}
+
+
+/**
+ * Places an object with a particle emitter. The emitter settings can be
+ * changed in the inspector afterwards.
+ *
+ * @return the uid of the new object, or a message if the layer's tileset has no such tile
+ */
+fn place_particle_generator(world: &mut GameWorld, id: usize, pos: Vector2<f64>) -> Result<u64, String> {
+    let map = &mut world.map;
+    let layer = MAP_OBJECT_LAYER;
+    let tileset_id = map.layer_info[layer].tileset_id;
+
+    let height =
+        match world.layer_tileset[tileset_id].tiles_by_id.get(&id) {
+            None => return Err(format!("Tileset {} has no tile {} for a particle generator", tileset_id, id)),
+            Some(tile) => tile.foot[1],
+        };
+
+    let mut mob = map.factory.create_mob(id, tileset_id, pos, height, 1.0);
+    let uid = mob.uid;
+
+    mob.visual.particles = ParticleDriver::with_emitter(EmitterSettings::default());
+    map.add_object(layer, mob);
 
-    map.layers[layer].insert(mob_id, mob);
+    Ok(uid)
 }
 
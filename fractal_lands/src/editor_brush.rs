@@ -0,0 +1,197 @@
+use rand::Rng;
+use rand::rngs::StdRng;
+use serde::Deserialize;
+use vecmath::{Vector2, vec2_sub, vec2_square_len};
+
+use crate::map::Map;
+
+
+/**
+ * Settings of the scatter brush. Missing entries in a brush config
+ * file are taken from the default config.
+ */
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct BrushConfig {
+    // world coordinates
+    pub radius: f64,
+
+    // objects per 100x100 world units, each time the brush touches the map
+    pub density: f64,
+
+    // new objects keep this distance to all objects on the layer
+    pub min_spacing: f64,
+
+    // scale and color of new objects are chosen randomly between these
+    pub scale: [f64; 2],
+    pub tint: [[f32; 4]; 2],
+
+    // tiles to scatter, one is chosen randomly for each object
+    pub tiles: Vec<usize>,
+}
+
+
+impl Default for BrushConfig {
+    fn default() -> Self {
+        BrushConfig {
+            radius: 120.0,
+            density: 0.5,
+            min_spacing: 32.0,
+            scale: [0.8, 1.2],
+            tint: [[0.85, 0.85, 0.85, 1.0], [1.0, 1.0, 1.0, 1.0]],
+            tiles: Vec::new(),
+        }
+    }
+}
+
+
+impl BrushConfig {
+
+    /**
+     * Reads a brush config file from the maps directory
+     */
+    pub fn load(filename: &str) -> Result<BrushConfig, String> {
+        let path = std::path::Path::new("maps").join(filename);
+        let content = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+
+        ron::from_str(&content).map_err(|error| error.to_string())
+    }
+}
+
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BrushMode {
+    Off,
+    Paint,
+    Erase,
+}
+
+
+impl BrushMode {
+
+    /**
+     * The brush key switches from off to paint to erase and back to off.
+     */
+    pub fn next(self) -> BrushMode {
+        match self {
+            BrushMode::Off => BrushMode::Paint,
+            BrushMode::Paint => BrushMode::Erase,
+            BrushMode::Erase => BrushMode::Off,
+        }
+    }
+}
+
+
+/**
+ * An object which the brush wants to place.
+ */
+pub struct ScatterItem {
+    pub tile_id: usize,
+    pub position: Vector2<f64>,
+    pub scale: f64,
+    pub color: [f32; 4],
+}
+
+
+/**
+ * Chooses random objects inside the brush circle. Positions which are too close
+ * to existing objects of the layer, or to each other, are left out.
+ */
+pub fn scatter(map: &Map, layer: usize, center: &Vector2<f64>, config: &BrushConfig, tiles: &[usize], rng: &mut StdRng) -> Vec<ScatterItem> {
+    let mut result: Vec<ScatterItem> = Vec::new();
+
+    if tiles.is_empty() {
+        return result;
+    }
+
+    // the fraction of an object is placed by chance
+    let expected = config.density * std::f64::consts::PI * config.radius * config.radius / 10000.0;
+    let count = expected.floor() as usize + if rng.gen::<f64>() < expected.fract() { 1 } else { 0 };
+    let spacing2 = config.min_spacing * config.min_spacing;
+
+    for _i in 0..count {
+        let distance = config.radius * rng.gen::<f64>().sqrt();
+        let angle = rng.gen::<f64>() * std::f64::consts::TAU;
+        let position = [center[0] + distance * angle.cos(), center[1] + distance * angle.sin()];
+
+        let tile_id = tiles[rng.gen_range(0..tiles.len())];
+        let scale = config.scale[0] + (config.scale[1] - config.scale[0]) * rng.gen::<f64>();
+        let t = rng.gen::<f32>();
+        let tint = &config.tint;
+        let color = std::array::from_fn(|i| tint[0][i] + (tint[1][i] - tint[0][i]) * t);
+
+        let crowded =
+            map.find_nearest_object(layer, &position, config.min_spacing, 0).is_some() ||
+            result.iter().any(|item| vec2_square_len(vec2_sub(item.position, position)) < spacing2);
+
+        if !crowded {
+            result.push(ScatterItem { tile_id, position, scale, color });
+        }
+    }
+
+    result
+}
+
+
+/**
+ * @return the objects of the layer inside the brush circle, except the player
+ */
+pub fn objects_to_erase(map: &Map, layer: usize, center: &Vector2<f64>, radius: f64) -> Vec<u64> {
+    map.find_objects_in_radius(layer, center, radius).into_iter()
+        .filter(|uid| *uid != map.player_id)
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use crate::map::{MAP_GROUND_LAYER, MAP_OBJECT_LAYER};
+
+
+    #[test]
+    fn test_scatter_spacing() {
+        let mut map = Map::new("", "", "");
+        let mut rng = StdRng::seed_from_u64(42);
+        let layer = MAP_GROUND_LAYER;
+        let center = [1000.0, 1000.0];
+
+        let config = BrushConfig {
+            density: 2.0,
+            min_spacing: 40.0,
+            ..BrushConfig::default()
+        };
+
+        for _i in 0..5 {
+            for item in scatter(&map, layer, &center, &config, &[7, 8], &mut rng) {
+                assert!(vec2_square_len(vec2_sub(item.position, center)) <= config.radius * config.radius);
+                assert!(item.scale >= 0.8 && item.scale <= 1.2);
+                assert!(item.tile_id == 7 || item.tile_id == 8);
+
+                let mob = map.factory.create_mob(item.tile_id, 0, item.position, 0.0, item.scale);
+                map.add_object(layer, mob);
+            }
+        }
+
+        assert!(!map.layers[layer].is_empty());
+
+        let positions: Vec<Vector2<f64>> = map.layers[layer].values().map(|mob| mob.position).collect();
+        for (i, a) in positions.iter().enumerate() {
+            for b in &positions[i + 1 ..] {
+                assert!(vec2_square_len(vec2_sub(*a, *b)) >= 40.0 * 40.0);
+            }
+        }
+
+        assert_eq!(objects_to_erase(&map, layer, &center, config.radius).len(), positions.len());
+    }
+
+
+    #[test]
+    fn test_eraser_keeps_player() {
+        let map = Map::new("", "", "");
+        let player_position = map.player_position();
+
+        assert!(objects_to_erase(&map, MAP_OBJECT_LAYER, &player_position, 100.0).is_empty());
+    }
+}
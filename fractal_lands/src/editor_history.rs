@@ -0,0 +1,398 @@
+use std::collections::VecDeque;
+
+use vecmath::Vector2;
+
+use crate::map::{Map, MapObject, MapTransition};
+use crate::collision::Footprint;
+use crate::gl_support::BlendMode;
+use crate::particle_driver::EmitterSettings;
+
+
+/**
+ * The editable properties of a map object. Undoing a modification
+ * restores these, everything else of the object stays untouched.
+ */
+#[derive(Clone, PartialEq)]
+pub struct ObjectState {
+    pub position: Vector2<f64>,
+    pub base_image_id: usize,
+    pub current_image_id: usize,
+    pub directions: usize,
+    pub tileset_id: usize,
+    pub height: f64,
+    pub scale: f64,
+    pub color: [f32; 4],
+    pub glow: [f32; 4],
+    pub blend: BlendMode,
+    pub footprint: Footprint,
+    pub emitter: Option<EmitterSettings>,
+}
+
+
+impl ObjectState {
+
+    pub fn of(mob: &MapObject) -> ObjectState {
+        let visual = &mob.visual;
+
+        ObjectState {
+            position: mob.position,
+            base_image_id: visual.base_image_id,
+            current_image_id: visual.current_image_id,
+            directions: visual.directions,
+            tileset_id: visual.tileset_id,
+            height: visual.height,
+            scale: visual.scale,
+            color: visual.color,
+            glow: visual.glow,
+            blend: visual.blend,
+            footprint: mob.footprint,
+            emitter: visual.particles.emitter().cloned(),
+        }
+    }
+
+
+    pub fn apply(&self, map: &mut Map, layer: usize, uid: u64) {
+        map.set_object_position(layer, uid, self.position);
+        map.apply_to_mob(layer, uid, |mob| {
+            let visual = &mut mob.visual;
+            visual.base_image_id = self.base_image_id;
+            visual.current_image_id = self.current_image_id;
+            visual.directions = self.directions;
+            visual.tileset_id = self.tileset_id;
+            visual.height = self.height;
+            visual.scale = self.scale;
+            visual.color = self.color;
+            visual.glow = self.glow;
+            visual.blend = self.blend;
+            visual.particles.set_emitter(self.emitter.clone());
+            mob.footprint = self.footprint;
+        });
+    }
+}
+
+
+/**
+ * One change of one map object. Inserted and removed objects are kept
+ * here while they are not on the map, so they can be put back as they were.
+ * Map transitions are few and small, they are kept as a whole.
+ */
+pub enum EditChange {
+    Insert { layer: usize, uid: u64, stash: Option<MapObject> },
+    Remove { layer: usize, uid: u64, stash: Option<MapObject> },
+    Modify { layer: usize, uid: u64, before: ObjectState, after: ObjectState },
+    Transitions { before: Vec<MapTransition>, after: Vec<MapTransition> },
+}
+
+
+impl EditChange {
+
+    fn revert(&mut self, map: &mut Map) {
+        match self {
+            EditChange::Insert { layer, uid, stash } => {
+                *stash = map.remove_object(*layer, *uid);
+            },
+            EditChange::Remove { layer, stash, .. } => {
+                if let Some(mob) = stash.take() {
+                    map.add_object(*layer, mob);
+                }
+            },
+            EditChange::Modify { layer, uid, before, .. } => {
+                before.apply(map, *layer, *uid);
+            },
+            EditChange::Transitions { before, .. } => {
+                map.transitions = before.clone();
+            },
+        }
+    }
+
+
+    fn replay(&mut self, map: &mut Map) {
+        match self {
+            EditChange::Insert { layer, stash, .. } => {
+                if let Some(mob) = stash.take() {
+                    map.add_object(*layer, mob);
+                }
+            },
+            EditChange::Remove { layer, uid, stash } => {
+                *stash = map.remove_object(*layer, *uid);
+            },
+            EditChange::Modify { layer, uid, after, .. } => {
+                after.apply(map, *layer, *uid);
+            },
+            EditChange::Transitions { after, .. } => {
+                map.transitions = after.clone();
+            },
+        }
+    }
+}
+
+
+/**
+ * Undo and redo history of the map editor. Each entry holds all changes
+ * of one editor action, so that e.g. deleting several objects can be
+ * undone in one step.
+ */
+pub struct EditHistory {
+    undo_stack: VecDeque<Vec<EditChange>>,
+    redo_stack: Vec<Vec<EditChange>>,
+
+    // maximum number of actions which can be undone
+    depth: usize,
+}
+
+
+impl EditHistory {
+
+    pub fn new(depth: usize) -> EditHistory {
+        EditHistory {
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
+            depth,
+        }
+    }
+
+
+    /**
+     * Forgets all actions, e.g. if another map was loaded.
+     */
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+    }
+
+
+    /**
+     * Records an action which was already applied to the map.
+     */
+    pub fn push(&mut self, changes: Vec<EditChange>) {
+        if changes.is_empty() || self.depth == 0 {
+            return;
+        }
+
+        self.redo_stack.clear();
+        self.undo_stack.push_back(changes);
+
+        if self.undo_stack.len() > self.depth {
+            self.undo_stack.pop_front();
+        }
+    }
+
+
+    /**
+     * Records an object which was just added to the map.
+     */
+    pub fn push_insert(&mut self, layer: usize, uid: u64) {
+        self.push_inserts(layer, &[uid]);
+    }
+
+
+    /**
+     * Records objects which were just added to the map, as one action.
+     */
+    pub fn push_inserts(&mut self, layer: usize, uids: &[u64]) {
+        self.push(uids.iter().map(|uid| EditChange::Insert { layer, uid: *uid, stash: None }).collect());
+    }
+
+
+    /**
+     * Removes the objects from the map, as one action which can be undone.
+     */
+    pub fn remove_objects(&mut self, map: &mut Map, layer: usize, uids: &[u64]) {
+        let mut changes = Vec::new();
+
+        for uid in uids {
+            let stash = map.remove_object(layer, *uid);
+            if stash.is_some() {
+                changes.push(EditChange::Remove { layer, uid: *uid, stash });
+            }
+        }
+
+        self.push(changes);
+    }
+
+
+    /**
+     * @return the current state of the objects, to be passed to commit_modifications()
+     *         once the objects were changed
+     */
+    pub fn capture(&self, map: &Map, layer: usize, uids: &[u64]) -> Vec<(u64, ObjectState)> {
+        uids.iter()
+            .filter_map(|uid| map.layers[layer].get(uid).map(|mob| (*uid, ObjectState::of(mob))))
+            .collect()
+    }
+
+
+    /**
+     * Records the differences between the captured and the current state of the objects.
+     */
+    pub fn commit_modifications(&mut self, map: &Map, layer: usize, captured: Vec<(u64, ObjectState)>) {
+        let mut changes = Vec::new();
+
+        for (uid, before) in captured {
+            if let Some(mob) = map.layers[layer].get(&uid) {
+                let after = ObjectState::of(mob);
+                if after != before {
+                    changes.push(EditChange::Modify { layer, uid, before, after });
+                }
+            }
+        }
+
+        self.push(changes);
+    }
+
+
+    /**
+     * Applies the function to the map and records how it changed the objects.
+     */
+    pub fn modify_objects<F>(&mut self, map: &mut Map, layer: usize, uids: &[u64], func: F)
+        where F: FnOnce(&mut Map) {
+        let captured = self.capture(map, layer, uids);
+        func(map);
+        self.commit_modifications(map, layer, captured);
+    }
+
+
+    /**
+     * Records the differences between the given and the current map transitions.
+     */
+    pub fn commit_transitions(&mut self, map: &Map, before: Vec<MapTransition>) {
+        if map.transitions != before {
+            self.push(vec![EditChange::Transitions { before, after: map.transitions.clone() }]);
+        }
+    }
+
+
+    /**
+     * Applies the function to the map transitions and records how it changed them.
+     */
+    pub fn modify_transitions<F>(&mut self, map: &mut Map, func: F)
+        where F: FnOnce(&mut Vec<MapTransition>) {
+        let before = map.transitions.clone();
+        func(&mut map.transitions);
+        self.commit_transitions(map, before);
+    }
+
+
+    /**
+     * @return true if there was an action to undo
+     */
+    pub fn undo(&mut self, map: &mut Map) -> bool {
+        match self.undo_stack.pop_back() {
+            None => false,
+            Some(mut changes) => {
+                for change in changes.iter_mut().rev() {
+                    change.revert(map);
+                }
+                self.redo_stack.push(changes);
+                true
+            }
+        }
+    }
+
+
+    /**
+     * @return true if there was an action to redo
+     */
+    pub fn redo(&mut self, map: &mut Map) -> bool {
+        match self.redo_stack.pop() {
+            None => false,
+            Some(mut changes) => {
+                for change in changes.iter_mut() {
+                    change.replay(map);
+                }
+                self.undo_stack.push_back(changes);
+                true
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::MAP_GROUND_LAYER;
+
+
+    #[test]
+    fn test_undo_redo() {
+        let mut map = Map::new("", "", "");
+        let mut history = EditHistory::new(10);
+        let layer = MAP_GROUND_LAYER;
+
+        let mob = map.factory.create_mob(5, layer, [100.0, 100.0], 0.0, 1.0);
+        let uid = mob.uid;
+        map.add_object(layer, mob);
+        history.push_insert(layer, uid);
+
+        history.modify_objects(&mut map, layer, &[uid], |map| {
+            map.set_object_position(layer, uid, [200.0, 300.0]);
+            map.apply_to_mob(layer, uid, |mob| mob.visual.scale = 2.0);
+        });
+
+        history.remove_objects(&mut map, layer, &[uid]);
+        assert!(map.layers[layer].is_empty());
+
+        assert!(history.undo(&mut map));
+        let mob = map.layers[layer].get(&uid).unwrap();
+        assert_eq!(mob.position, [200.0, 300.0]);
+        assert_eq!(mob.visual.scale, 2.0);
+        assert_eq!(map.find_nearest_object(layer, &[200.0, 300.0], 10.0, 0), Some(uid));
+
+        assert!(history.undo(&mut map));
+        assert_eq!(map.layers[layer].get(&uid).unwrap().position, [100.0, 100.0]);
+
+        assert!(history.undo(&mut map));
+        assert!(map.layers[layer].is_empty());
+        assert!(!history.undo(&mut map));
+
+        assert!(history.redo(&mut map));
+        assert!(history.redo(&mut map));
+        assert_eq!(map.layers[layer].get(&uid).unwrap().visual.scale, 2.0);
+    }
+
+
+    #[test]
+    fn test_undo_transitions() {
+        let mut map = Map::new("", "", "");
+        let mut history = EditHistory::new(10);
+
+        let transit = MapTransition { from: [100.0, 100.0], rad: 50.0, to_map: "other.map".to_string(), to_position: [0.0, 0.0], facing: 0 };
+        history.modify_transitions(&mut map, |transitions| transitions.push(transit));
+        history.modify_transitions(&mut map, |transitions| transitions[0].rad = 80.0);
+        history.modify_transitions(&mut map, |transitions| { transitions.remove(0); });
+        assert!(map.transitions.is_empty());
+
+        // nothing changed, nothing to undo
+        history.modify_transitions(&mut map, |_transitions| {});
+
+        assert!(history.undo(&mut map));
+        assert_eq!(map.transitions[0].rad, 80.0);
+        assert!(history.undo(&mut map));
+        assert_eq!(map.transitions[0].rad, 50.0);
+        assert!(history.undo(&mut map));
+        assert!(map.transitions.is_empty());
+
+        assert!(history.redo(&mut map));
+        assert_eq!(map.transitions.len(), 1);
+    }
+
+
+    #[test]
+    fn test_depth_limit() {
+        let mut map = Map::new("", "", "");
+        let mut history = EditHistory::new(2);
+        let layer = MAP_GROUND_LAYER;
+
+        for i in 0..3 {
+            let mob = map.factory.create_mob(5, layer, [i as f64 * 100.0, 0.0], 0.0, 1.0);
+            history.push_insert(layer, mob.uid);
+            map.add_object(layer, mob);
+        }
+
+        assert!(history.undo(&mut map));
+        assert!(history.undo(&mut map));
+        assert!(!history.undo(&mut map));
+        assert_eq!(map.layers[layer].len(), 1);
+    }
+}
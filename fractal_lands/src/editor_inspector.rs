@@ -0,0 +1,481 @@
+use vecmath::Vector2;
+
+use glium::Program;
+use glium::Frame;
+
+use crate::ui::{UI, UiComponent, ButtonEvent, Button};
+use crate::map::Map;
+use crate::map_file::format_number;
+use crate::editor_history::ObjectState;
+use crate::gl_support::BlendMode;
+use crate::particle_driver::EmitterSettings;
+
+
+// component ids of the inspector fields
+const X_FIELD_ID: usize = 4000;
+const Y_FIELD_ID: usize = 4001;
+const SCALE_FIELD_ID: usize = 4002;
+const HEIGHT_FIELD_ID: usize = 4003;
+const DIRECTIONS_FIELD_ID: usize = 4004;
+const IMAGE_FIELD_ID: usize = 4005;
+const TILESET_FIELD_ID: usize = 4006;
+const COLOR_FIELD_ID: usize = 4007;
+const GLOW_FIELD_ID: usize = 4008;
+const BLEND_BUTTON_ID: usize = 4009;
+const EMITTER_BUTTON_ID: usize = 4010;
+const SPAWN_IDS_FIELD_ID: usize = 4011;
+const PARTICLE_TILESET_FIELD_ID: usize = 4012;
+const RATE_FIELD_ID: usize = 4013;
+const PARTICLE_POSITION_FIELD_ID: usize = 4014;
+const VELOCITY_MIN_FIELD_ID: usize = 4015;
+const VELOCITY_MAX_FIELD_ID: usize = 4016;
+const LIFETIME_FIELD_ID: usize = 4017;
+const PARTICLE_COLOR_FIELD_ID: usize = 4018;
+
+const PANEL_WIDTH: i32 = 260;
+const PANEL_TOP: i32 = 60;
+const LINE_SPACE: i32 = 28;
+
+
+/**
+ * A panel at the right side of the window, which shows the properties
+ * of the selected map object and lets the user type in new values.
+ */
+pub struct Inspector {
+    panel: Option<UiComponent>,
+
+    // layer, uid and state of the object which the panel currently shows
+    shown: Option<(usize, u64, ObjectState)>,
+}
+
+
+impl Inspector {
+
+    pub fn new() -> Inspector {
+        Inspector {
+            panel: None,
+            shown: None,
+        }
+    }
+
+
+    pub fn toggle(&mut self, ui: &UI, map: &Map) {
+        if self.panel.is_some() {
+            self.panel = None;
+        }
+        else {
+            self.rebuild(ui, map);
+        }
+    }
+
+
+    pub fn has_focus(&self) -> bool {
+        self.panel.as_ref().is_some_and(|panel| panel.head.has_focus())
+    }
+
+
+    pub fn contains(&self, position: &Vector2<f64>) -> bool {
+        self.panel.as_ref().is_some_and(|panel| panel.head.area().contains(position[0] as i32, position[1] as i32))
+    }
+
+
+    /**
+     * @return true if the event was meant for the panel
+     */
+    pub fn takes_event(&self, event: &ButtonEvent) -> bool {
+        match event.args.button {
+            Button::Keyboard(_) => self.has_focus(),
+            Button::Mouse(_) => self.contains(&[event.mx, event.my]),
+        }
+    }
+
+
+    /**
+     * Passes the event to the panel.
+     *
+     * @return id and text of the component which was triggered
+     */
+    pub fn handle_button_event(&mut self, event: &ButtonEvent) -> Option<(usize, String)> {
+        let panel = self.panel.as_mut()?;
+        let comp = panel.head.handle_button_event(event)?;
+
+        Some((comp.get_id(), comp.get_text_result()))
+    }
+
+
+    /**
+     * Takes the keyboard focus away from the panel, so that the keys work as hotkeys again.
+     */
+    pub fn release_focus(&mut self) {
+        if let Some(panel) = &mut self.panel {
+            panel.head.set_focus(false);
+        }
+    }
+
+
+    /**
+     * @return layer and uid of the inspected object
+     */
+    pub fn target(&self) -> Option<(usize, u64)> {
+        self.shown.as_ref().map(|(layer, uid, _)| (*layer, *uid))
+    }
+
+
+    /**
+     * Rebuilds the panel if the selection or the selected object changed. While the
+     * user is typing, the panel is only rebuilt if another object was selected.
+     */
+    pub fn sync(&mut self, ui: &UI, map: &Map) {
+        if self.panel.is_none() {
+            return;
+        }
+
+        let current = inspected_object(map);
+        let changed =
+            match (&self.shown, &current) {
+                (None, None) => false,
+                (Some((layer, uid, state)), Some((current_layer, current_uid, current_state))) => {
+                    if layer != current_layer || uid != current_uid {
+                        true
+                    }
+                    else {
+                        state != current_state && !self.has_focus()
+                    }
+                },
+                _ => true,
+            };
+
+        if changed {
+            self.rebuild(ui, map);
+        }
+    }
+
+
+    fn rebuild(&mut self, ui: &UI, map: &Map) {
+        self.shown = inspected_object(map);
+        self.panel = Some(make_panel(ui, &self.shown, map.selection.len()));
+    }
+
+
+    pub fn draw(&self, ui: &mut UI, target: &mut Frame, program: &Program) {
+        if let Some(panel) = &self.panel {
+            panel.head.draw(&ui.display, target, program, &mut ui.context, 0, 0);
+        }
+    }
+}
+
+
+/**
+ * The inspector shows an object only if it is the only one selected.
+ */
+fn inspected_object(map: &Map) -> Option<(usize, u64, ObjectState)> {
+    if map.selection.len() != 1 {
+        return None;
+    }
+
+    let layer = map.selected_layer;
+    let uid = map.selection[0];
+
+    map.layers[layer].get(&uid).map(|mob| (layer, uid, ObjectState::of(mob)))
+}
+
+
+fn make_panel(ui: &UI, shown: &Option<(usize, u64, ObjectState)>, selection_count: usize) -> UiComponent {
+    let ww = ui.context.window_size[0] as i32;
+    let x = ww - PANEL_WIDTH - 10;
+
+    let (uid, state) =
+        match shown {
+            None => {
+                let mut panel = ui.make_container(x, PANEL_TOP, PANEL_WIDTH, 60);
+                let message = if selection_count == 0 { "No object selected".to_string() } else { selection_count.to_string() + " objects selected" };
+                panel.head.add_child(ui.make_button(0, 0, PANEL_WIDTH, 60, &message, 0));
+                return panel;
+            },
+            Some((_layer, uid, state)) => (uid, state),
+        };
+
+    let fields = [
+        ("X", format_number(state.position[0]), X_FIELD_ID),
+        ("Y", format_number(state.position[1]), Y_FIELD_ID),
+        ("Scale", format_number(state.scale), SCALE_FIELD_ID),
+        ("Height", format_number(state.height), HEIGHT_FIELD_ID),
+        ("Directions", state.directions.to_string(), DIRECTIONS_FIELD_ID),
+        ("Image", state.base_image_id.to_string(), IMAGE_FIELD_ID),
+        ("Tileset", state.tileset_id.to_string(), TILESET_FIELD_ID),
+        ("Color", format_color(&state.color), COLOR_FIELD_ID),
+        ("Glow", format_color(&state.glow), GLOW_FIELD_ID),
+    ];
+
+    // the emitter settings are only shown if the object has an emitter
+    let emitter_fields = match &state.emitter {
+        None => Vec::new(),
+        Some(emitter) => vec![
+            ("Particles", format_ids(&emitter.spawn_ids), SPAWN_IDS_FIELD_ID),
+            ("P. tileset", emitter.tileset_id.to_string(), PARTICLE_TILESET_FIELD_ID),
+            ("Rate", format_number(emitter.rate), RATE_FIELD_ID),
+            ("P. position", format_numbers(&emitter.position), PARTICLE_POSITION_FIELD_ID),
+            ("Velocity min", format_numbers(&emitter.velocity_min), VELOCITY_MIN_FIELD_ID),
+            ("Velocity max", format_numbers(&emitter.velocity_max), VELOCITY_MAX_FIELD_ID),
+            ("Lifetime", format_numbers(&emitter.lifetime), LIFETIME_FIELD_ID),
+            ("P. color", format_numbers(&emitter.color.map(|c| c as f64)), PARTICLE_COLOR_FIELD_ID),
+        ],
+    };
+
+    let h = (fields.len() + emitter_fields.len() + 3) as i32 * LINE_SPACE + 10;
+    let mut panel = ui.make_container(x, PANEL_TOP, PANEL_WIDTH, h);
+    let mut y = 5;
+
+    panel.head.add_child(ui.make_label(10, y, PANEL_WIDTH - 20, 24, &("Object ".to_string() + &uid.to_string())));
+    y += LINE_SPACE;
+
+    for (label, text, id) in fields {
+        add_field(ui, &mut panel, y, label, &text, id);
+        y += LINE_SPACE;
+    }
+
+    let blend = if state.blend == BlendMode::Add { "Blend: Addition" } else { "Blend: Mix" };
+    panel.head.add_child(ui.make_button(10, y, PANEL_WIDTH - 20, 24, blend, BLEND_BUTTON_ID));
+    y += LINE_SPACE;
+
+    let emitter = if state.emitter.is_some() { "Particle emitter: On" } else { "Particle emitter: Off" };
+    panel.head.add_child(ui.make_button(10, y, PANEL_WIDTH - 20, 24, emitter, EMITTER_BUTTON_ID));
+    y += LINE_SPACE;
+
+    for (label, text, id) in emitter_fields {
+        add_field(ui, &mut panel, y, label, &text, id);
+        y += LINE_SPACE;
+    }
+
+    panel
+}
+
+
+/**
+ * @return true if the component is a button, which acts on a click
+ */
+pub fn is_button(id: usize) -> bool {
+    id == BLEND_BUTTON_ID || id == EMITTER_BUTTON_ID
+}
+
+
+fn add_field(ui: &UI, panel: &mut UiComponent, y: i32, label: &str, text: &str, id: usize) {
+    let mut field = ui.make_text_field(100, y, PANEL_WIDTH - 110, 24, text, id);
+    field.head.set_focus(false);
+
+    panel.head.add_child(ui.make_label(10, y, 90, 24, label));
+    panel.head.add_child(field);
+}
+
+
+fn format_numbers<const N: usize>(values: &[f64; N]) -> String {
+    values.iter().map(|v| format_number(*v)).collect::<Vec<String>>().join(" ")
+}
+
+
+fn format_color(color: &[f32; 4]) -> String {
+    format_numbers(&color.map(|c| c as f64))
+}
+
+
+fn format_ids(ids: &[usize]) -> String {
+    ids.iter().map(|id| id.to_string()).collect::<Vec<String>>().join(" ")
+}
+
+
+/**
+ * Parses a list of values, separated by spaces or commas.
+ */
+fn parse_list<T: std::str::FromStr>(text: &str, name: &str) -> Result<Vec<T>, String> {
+    text.split([' ', ','])
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse::<T>().map_err(|_| format!("'{}' is no valid {}", text, name)))
+        .collect()
+}
+
+
+fn parse_numbers<const N: usize>(text: &str, name: &str) -> Result<[f64; N], String> {
+    let values = parse_list::<f64>(text, name)?;
+
+    values.try_into().map_err(|_| format!("'{}' is no valid {}, expected {} numbers", text, name, N))
+}
+
+
+/**
+ * Colors are typed as numbers from 0.0 to 1.0, separated by spaces or commas.
+ */
+fn parse_color<const N: usize>(text: &str) -> Result<[f32; N], String> {
+    let values = parse_numbers::<N>(text, "color")?;
+
+    if values.iter().any(|v| *v < 0.0 || *v > 1.0) {
+        return Err(format!("'{}' is no color, expected {} numbers from 0 to 1", text, N));
+    }
+
+    Ok(values.map(|v| v as f32))
+}
+
+
+fn parse_number<T: std::str::FromStr>(text: &str, name: &str) -> Result<T, String> {
+    text.trim().parse::<T>().map_err(|_| format!("'{}' is no valid {}", text, name))
+}
+
+
+/**
+ * Applies the text of an inspector field to the object state.
+ *
+ * @param tile_exists tells if a tileset has a tile with the given id
+ * @return the changed state, or a message why the text was refused
+ */
+pub fn edited_state<F>(state: &ObjectState, id: usize, text: &str, tile_exists: F) -> Result<ObjectState, String>
+    where F: Fn(usize, usize) -> bool {
+
+    let mut result = state.clone();
+
+    match id {
+        X_FIELD_ID => result.position[0] = parse_number(text, "x position")?,
+        Y_FIELD_ID => result.position[1] = parse_number(text, "y position")?,
+        SCALE_FIELD_ID => {
+            result.scale = parse_number(text, "scale")?;
+            if result.scale <= 0.0 {
+                return Err("The scale must be greater than 0".to_string());
+            }
+        },
+        HEIGHT_FIELD_ID => result.height = parse_number(text, "height")?,
+        DIRECTIONS_FIELD_ID => {
+            result.directions = parse_number(text, "number of directions")?;
+            if result.directions == 0 {
+                return Err("An object needs at least one direction".to_string());
+            }
+        },
+        IMAGE_FIELD_ID => {
+            // the field shows the base image, the object keeps facing the same way
+            let facing = state.current_image_id.saturating_sub(state.base_image_id);
+            result.base_image_id = parse_number(text, "tile id")?;
+            result.current_image_id = result.base_image_id + facing;
+            if !tile_exists(result.tileset_id, result.current_image_id) {
+                return Err(format!("Tileset {} has no tile {}", result.tileset_id, result.current_image_id));
+            }
+        },
+        TILESET_FIELD_ID => {
+            result.tileset_id = parse_number(text, "tileset id")?;
+            if !tile_exists(result.tileset_id, result.current_image_id) {
+                return Err(format!("Tileset {} has no tile {}", result.tileset_id, result.current_image_id));
+            }
+        },
+        COLOR_FIELD_ID => result.color = parse_color(text)?,
+        GLOW_FIELD_ID => result.glow = parse_color(text)?,
+        BLEND_BUTTON_ID => {
+            result.blend = if state.blend == BlendMode::Add { BlendMode::Blend } else { BlendMode::Add };
+        },
+        EMITTER_BUTTON_ID => {
+            result.emitter = if state.emitter.is_some() { None } else { Some(EmitterSettings::default()) };
+        },
+        _ => {
+            if let Some(emitter) = &mut result.emitter {
+                edit_emitter(emitter, id, text, tile_exists)?;
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+
+fn edit_emitter<F>(emitter: &mut EmitterSettings, id: usize, text: &str, tile_exists: F) -> Result<(), String>
+    where F: Fn(usize, usize) -> bool {
+
+    match id {
+        SPAWN_IDS_FIELD_ID => emitter.spawn_ids = parse_list(text, "list of tile ids")?,
+        PARTICLE_TILESET_FIELD_ID => emitter.tileset_id = parse_number(text, "tileset id")?,
+        RATE_FIELD_ID => {
+            emitter.rate = parse_number(text, "rate")?;
+            if emitter.rate < 0.0 {
+                return Err("The rate must not be negative".to_string());
+            }
+        },
+        PARTICLE_POSITION_FIELD_ID => emitter.position = parse_numbers(text, "position")?,
+        VELOCITY_MIN_FIELD_ID => emitter.velocity_min = parse_numbers(text, "velocity")?,
+        VELOCITY_MAX_FIELD_ID => emitter.velocity_max = parse_numbers(text, "velocity")?,
+        LIFETIME_FIELD_ID => {
+            emitter.lifetime = parse_numbers(text, "lifetime range")?;
+            if emitter.lifetime[0] < 0.0 || emitter.lifetime[0] > emitter.lifetime[1] {
+                return Err("The lifetime needs a minimum and a larger maximum, in seconds".to_string());
+            }
+        },
+        PARTICLE_COLOR_FIELD_ID => emitter.color = parse_color(text)?,
+        _ => {}
+    }
+
+    // particles of missing tiles can't be drawn
+    if let Some(tile) = emitter.spawn_ids.iter().find(|tile| !tile_exists(emitter.tileset_id, **tile)) {
+        return Err(format!("Tileset {} has no tile {}", emitter.tileset_id, tile));
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collision::Footprint;
+
+
+    fn test_state() -> ObjectState {
+        ObjectState {
+            position: [100.0, 200.0],
+            base_image_id: 5,
+            current_image_id: 5,
+            directions: 8,
+            tileset_id: 1,
+            height: 10.0,
+            scale: 1.0,
+            color: [1.0, 1.0, 1.0, 1.0],
+            glow: [1.0, 1.0, 1.0, 1.0],
+            blend: BlendMode::Blend,
+            footprint: Footprint::FromTile,
+            emitter: None,
+        }
+    }
+
+
+    #[test]
+    fn test_edit_fields() {
+        let state = test_state();
+        let any_tile = |_set: usize, _id: usize| true;
+
+        assert_eq!(edited_state(&state, X_FIELD_ID, " 123.5", any_tile).unwrap().position, [123.5, 200.0]);
+        assert_eq!(edited_state(&state, SCALE_FIELD_ID, "0.37", any_tile).unwrap().scale, 0.37);
+        assert_eq!(edited_state(&state, COLOR_FIELD_ID, "0.5, 0.25 1 1", any_tile).unwrap().color, [0.5, 0.25, 1.0, 1.0]);
+        assert_eq!(edited_state(&state, BLEND_BUTTON_ID, "", any_tile).unwrap().blend, BlendMode::Add);
+
+        assert!(edited_state(&state, SCALE_FIELD_ID, "-1", any_tile).is_err());
+        assert!(edited_state(&state, DIRECTIONS_FIELD_ID, "many", any_tile).is_err());
+        assert!(edited_state(&state, GLOW_FIELD_ID, "1 1 1", any_tile).is_err());
+        assert!(edited_state(&state, IMAGE_FIELD_ID, "7", |_set, id| id == 5).is_err());
+
+        let facing_left = ObjectState { current_image_id: 7, ..test_state() };
+        let image = edited_state(&facing_left, IMAGE_FIELD_ID, "20", any_tile).unwrap();
+        assert_eq!((image.base_image_id, image.current_image_id), (20, 22));
+    }
+
+
+    #[test]
+    fn test_edit_emitter() {
+        let any_tile = |_set: usize, _id: usize| true;
+        let state = edited_state(&test_state(), EMITTER_BUTTON_ID, "", any_tile).unwrap();
+        assert_eq!(state.emitter, Some(EmitterSettings::default()));
+
+        let state = edited_state(&state, SPAWN_IDS_FIELD_ID, "3 4, 5", any_tile).unwrap();
+        let state = edited_state(&state, VELOCITY_MAX_FIELD_ID, "1 -2 3.5", any_tile).unwrap();
+        let emitter = state.emitter.as_ref().unwrap();
+        assert_eq!(emitter.spawn_ids, vec![3, 4, 5]);
+        assert_eq!(emitter.velocity_max, [1.0, -2.0, 3.5]);
+
+        assert!(edited_state(&state, LIFETIME_FIELD_ID, "2 1", any_tile).is_err());
+        assert!(edited_state(&state, VELOCITY_MIN_FIELD_ID, "1 2", any_tile).is_err());
+        assert!(edited_state(&state, PARTICLE_TILESET_FIELD_ID, "9", |set, _id| set != 9).is_err());
+
+        assert_eq!(edited_state(&state, EMITTER_BUTTON_ID, "", any_tile).unwrap().emitter, None);
+    }
+}
@@ -14,7 +14,6 @@ use crate::map::MoveEndAction;
 use crate::map::MapObject;
 use crate::map::MapObjectFactory;
 use crate::map::MobType;
-use crate::MAP_RESOURCE_PATH;
 use crate::MAP_OBJECT_LAYER;
 use crate::PROJECTILE_TILESET;
 
@@ -43,7 +42,7 @@ impl UiController for Game {
             match comp {
                 None => {
 
-                    let pos = screen_to_world_pos(&ui, &world.map.player_position(), &ui.context.mouse_state.position);
+                    let pos = screen_to_world_pos(&ui, &world.camera, &ui.context.mouse_state.position);
                     
                     if event.args.button == Button::Mouse(MouseButton::Left) {
                         ui.root.head.clear();
@@ -54,7 +53,7 @@ impl UiController for Game {
                         match option {
                             None => {
                                 // nothing clicked -> move player
-                                map.has_selection = false;
+                                map.selection.clear();
                             },
                             Some(_idx) => {
                                 // pick up the item?
@@ -73,7 +72,7 @@ impl UiController for Game {
 
                         let mut projectile = fire_projectile(player.position, pos, MobType::PlayerProjectile, factory);
                         map.projectile_builder.configure_projectile("Fireball", &mut projectile.visual, &mut projectile.velocity, &mut world.speaker);
-                        map.layers[MAP_OBJECT_LAYER].insert(projectile.uid, projectile);
+                        map.add_object(MAP_OBJECT_LAYER, projectile);
                     }
 
                     if event.args.button == Button::Keyboard(Key::Character("i".into())) {
@@ -142,14 +141,10 @@ impl UiController for Game {
         let reload = map.check_player_transition(rng);
 
         if reload {
-/*
-            let map_texture = Texture::from_path(Path::new(&(MAP_RESOURCE_PATH.to_string() + &map.map_image_name)), &TextureSettings::new()).unwrap();
-            let map_backdrop = Texture::from_path(Path::new(&(MAP_RESOURCE_PATH.to_string() + &map.backdrop_image_name)), &TextureSettings::new()).unwrap();
- 
-            world.map_texture = map_texture;
-            world.map_backdrop = map_backdrop;
-*/
+            world.reload_map_textures();
         }
+
+        world.camera.follow(world.map.player_position());
     }
 }
 
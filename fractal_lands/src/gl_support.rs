@@ -1,5 +1,7 @@
 use std::io::BufReader;
 use std::fs::File;
+use std::rc::Rc;
+use std::collections::HashMap;
 
 use glutin::surface::SurfaceTypeTrait;
 use glutin::surface::ResizeableSurface;
@@ -17,6 +19,8 @@ use glium::Rect;
 use glium::implement_vertex;
 use glium::uniform;
 
+use serde::{Serialize, Deserialize};
+
 use crate::ui::UiArea;
 
 #[derive(Copy, Clone)]
@@ -27,7 +31,7 @@ pub struct Vertex {
 implement_vertex!(Vertex, position, tex_coords);
 
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub enum BlendMode {
     Blend,
     Add,
@@ -59,6 +63,33 @@ pub fn load_texture<T: SurfaceTypeTrait + ResizeableSurface>(display: &Display<T
     texture
 }
 
+/**
+ * Keeps textures which have been loaded once, so that switching back and forth
+ * between maps does not read the same image files again and again.
+ */
+pub struct TextureCache {
+    display: Display<WindowSurface>,
+    textures: HashMap<String, Rc<Texture2d>>,
+}
+
+impl TextureCache {
+    pub fn new(display: &Display<WindowSurface>) -> TextureCache {
+        TextureCache {
+            display: display.clone(),
+            textures: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, filename: &str) -> Rc<Texture2d> {
+        let texture = self.textures.entry(filename.to_string()).or_insert_with(|| {
+            println!("Loading texture {}", filename);
+            Rc::new(load_texture(&self.display, filename))
+        });
+
+        texture.clone()
+    }
+}
+
 pub fn texture_from_data<T: SurfaceTypeTrait + ResizeableSurface>(display: &Display<T>, data: Vec<u8>, width: u32, height: u32) -> glium::Texture2d {
 
     let image = glium::texture::RawImage2d::from_raw_rgba(data, (width, height));
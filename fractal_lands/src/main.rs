@@ -15,9 +15,10 @@ use glium::Program;
 use glium::winit::keyboard::Key;
 use glium::winit::event::MouseScrollDelta;
 
-use vecmath::{vec2_add, vec2_len, vec2_scale, vec2_sub, Vector2};
+use vecmath::{vec2_len, vec2_sub, Vector2};
 use rand::SeedableRng;
 
+use std::rc::Rc;
 use std::time::SystemTime;
 use std::fs::read_to_string;
 use std::path::Path;
@@ -28,7 +29,11 @@ mod creature;
 mod inventory;
 mod projectile;
 mod map;
+mod camera;
 mod editor;
+mod editor_history;
+mod editor_inspector;
+mod editor_brush;
 mod game;
 mod ui;
 mod sound;
@@ -37,9 +42,19 @@ mod animation;
 mod mob_group;
 mod player_inventory_view;
 mod gl_support;
-
-use map::{Map, MAP_GROUND_LAYER, MAP_OBJECT_LAYER, MAP_CLOUD_LAYER};
+mod spatial_index;
+mod collision;
+mod pathfinding;
+mod map_file;
+mod server_map_import;
+mod map_generator;
+mod tile_palette;
+mod autosave;
+
+use map::{Map, MapSnapshot, move_mob_along_path, MAP_OBJECT_LAYER};
+use map_file::MapFormat;
 use ui::{UI, UiController, TileSet, Button, ButtonState, ButtonArgs, MouseButton, ButtonEvent, MouseMoveEvent, ScrollEvent};
+use camera::Camera;
 use editor::MapEditor;
 use game::Game;
 use item::ItemFactory;
@@ -48,6 +63,7 @@ use sound::SoundPlayer;
 
 use gl_support::BlendMode;
 use gl_support::load_texture;
+use gl_support::TextureCache;
 use gl_support::build_program;
 use gl_support::draw_texture;
 
@@ -55,12 +71,13 @@ const MAP_RESOURCE_PATH: &str = "resources/map/";
 const CREATURE_TILESET: usize = 3;
 const PROJECTILE_TILESET: usize = 5;
 const ANIMATION_TILESET: usize = 7;
+const TILESET_COUNT: usize = 8;
 
 // Game structures
 
 pub struct GameWorld {
     map: Map,
-    layer_tileset: [TileSet; 8],
+    layer_tileset: [TileSet; TILESET_COUNT],
 
     player_inventory: Inventory,
 
@@ -68,8 +85,25 @@ pub struct GameWorld {
 
     rng: rand::rngs::StdRng,
 
-    map_texture: Texture2d,
-    map_backdrop: Texture2d,
+    // the editor moves the camera freely, in game mode it follows the player
+    camera: Camera,
+
+    map_textures: TextureCache,
+    map_texture: Rc<Texture2d>,
+    map_backdrop: Rc<Texture2d>,
+}
+
+
+impl GameWorld {
+
+    /**
+     * Fetches map image and backdrop for the current map. Must be called
+     * after the map was changed.
+     */
+    pub fn reload_map_textures(&mut self) {
+        self.map_texture = self.map_textures.get(&(MAP_RESOURCE_PATH.to_string() + &self.map.map_image_name));
+        self.map_backdrop = self.map_textures.get(&(MAP_RESOURCE_PATH.to_string() + &self.map.backdrop_image_name));
+    }
 }
 
 
@@ -77,6 +111,9 @@ pub struct GameControllers {
     editor: MapEditor,
     game: Game,
     edit: bool,    
+
+    // the map and view of the editor, while the map is played
+    play_start: Option<(MapSnapshot, Camera)>,
 }
 
 
@@ -109,9 +146,6 @@ impl App {
         let map_image_file = "map_wasteland.png";
         let map_backdrop_file = "backdrop_red_blue.png";
 
-        let map_texture = load_texture(&display, &(MAP_RESOURCE_PATH.to_string() + map_image_file));
-        let map_backdrop = load_texture(&display, &(MAP_RESOURCE_PATH.to_string() + map_backdrop_file));
-
         let ground_tiles = TileSet::load(&display, "../tiny_places_client/resources/grounds", "map_objects.tica");
         let decoration_tiles = TileSet::load(&display, "../tiny_places_client/resources/objects", "map_objects.tica");
         let item_tiles = TileSet::load(&display, "../tiny_places_client/resources/items", "items.tica");
@@ -132,13 +166,24 @@ impl App {
             animation_tiles,
             ];        
 
-        let rng = rand::rngs::StdRng::seed_from_u64(12345678901);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(12345678901);
         let mut map = Map::new("Demo Map", map_image_file, map_backdrop_file);
-        map.load("start.map");
+        map.set_tile_footprints(&layer_tileset);
+        
+        if let Err(error) = map.load("start.map", &mut rng) {
+            println!("Error: {}", error);
+        }
+
+        let camera = Camera::new(map.player_position());
 
-        let ui = UI::new(display, window_size);
+        let mut map_textures = TextureCache::new(&display);
+        let map_texture = map_textures.get(&(MAP_RESOURCE_PATH.to_string() + &map.map_image_name));
+        let map_backdrop = map_textures.get(&(MAP_RESOURCE_PATH.to_string() + &map.backdrop_image_name));
+
+        let mut ui = UI::new(display, window_size);
         
-        let editor = MapEditor::new();
+        let mut editor = MapEditor::new();
+        editor.offer_recovery(&mut ui);
 
         let inventory_bg = load_texture(&ui.display, "resources/ui/inventory_bg.png");
         let game = Game::new(inventory_bg, &ui, &layer_tileset[6]);
@@ -171,6 +216,9 @@ impl App {
 
                 rng,
 
+                camera,
+
+                map_textures,
                 map_texture,
                 map_backdrop,
             },
@@ -179,6 +227,7 @@ impl App {
                 editor,
                 game,
                 edit: true,
+                play_start: None,
             },
 
             update_time: SystemTime::now(),
@@ -210,16 +259,18 @@ impl App {
         let width = self.ui.context.window_size[0] as f32;
         let height = self.ui.context.window_size[1] as f32;
 
-        let player_position = &world.map.player_position();
-        let player_x = player_position[0] as f32;
-        let player_y = player_position[1] as f32;
+        let camera = &world.camera;
+        let zoom = camera.zoom as f32;
+        let camera_x = camera.position[0] as f32;
+        let camera_y = camera.position[1] as f32;
 
-        let offset_x = width / 2.0 - player_x;
-        let offset_y = height / 2.0 - player_y / 2.0;
+        let offset_x = width / 2.0 - camera_x * zoom;
+        let offset_y = height / 2.0 - camera_y * zoom / 2.0;
 
         // background image, parallax scrolling at 0.5 times map scroll amount
-        let back_off_x = - player_x / 2.0;
-        let back_off_y = - player_y / 4.0;
+        // the backdrop is far away, it doesn't zoom
+        let back_off_x = - camera_x / 2.0;
+        let back_off_y = - camera_y / 4.0;
 
         let mut target = self.ui.display.draw();
         // target.clear_color(0.0, 0.0, 1.0, 1.0);
@@ -228,18 +279,20 @@ impl App {
                      back_off_x, back_off_y, 2.0, 2.0, &[0.8, 0.8, 0.8, 1.0]);
 
         draw_texture(&self.ui.display, &mut target, program, BlendMode::Blend, &self.world.map_texture, 
-                     offset_x, offset_y, 2.0, 2.0, &[0.8, 0.8, 0.8, 1.0]);
+                     offset_x, offset_y, 2.0 * zoom, 2.0 * zoom, &[0.8, 0.8, 0.8, 1.0]);
 
         let tex_white = &self.ui.context.tex_white;
 
-        // draw ground decorations (flat)
-        Self::render_layer(&self.ui.display, &mut target, program, world, tex_white, MAP_GROUND_LAYER);
-
-        // draw decorations (upright things)
-        Self::render_layer(&self.ui.display, &mut target, program, world, tex_white, MAP_OBJECT_LAYER);
+        // ground decorations (flat), then decorations (upright things), then clouds and
+        // whatever is on the upper layers
+        // layers can be hidden while editing the map
+        for layer_id in 0..world.map.layers.len() {
+            let hidden = self.controllers.edit && world.map.layer_flags[layer_id].hidden;
 
-        // draw clouds
-        Self::render_layer(&self.ui.display, &mut target, program, world, tex_white, MAP_CLOUD_LAYER);
+            if world.map.layer_info[layer_id].visible && !hidden {
+                Self::render_layer(&self.ui.display, &mut target, program, world, tex_white, layer_id);
+            }
+        }
 
         {
             let world = &mut self.world;
@@ -258,7 +311,8 @@ impl App {
         let (width, height) = display.get_framebuffer_dimensions();
         let window_center = [width as f64 * 0.5, height as f64 * 0.5];
 
-        let player_position = &world.map.player_position();
+        let camera = &world.camera;
+        let zoom = camera.zoom as f32;
         let mut objects = Vec::new();
 
         for (_key, mob) in &world.map.layers[layer_id] {
@@ -286,29 +340,27 @@ impl App {
             let set = &world.layer_tileset[tileset_id];                    
             let tile = set.tiles_by_id.get(&mob.visual.current_image_id).unwrap();
 
-            let tpos = calc_tile_position(&mob.position, tile.foot, mob.visual.scale, player_position, &window_center);
+            let tpos = calc_tile_position(&mob.position, tile.foot, mob.visual.scale, camera, &window_center);
 
             draw_texture(display, target, program,
                 mob.visual.blend,
                 &tile.tex,
                 tpos[0],
                 tpos[1], 
-                mob.visual.scale as f32, 
-                mob.visual.scale as f32,
+                mob.visual.scale as f32 * zoom, 
+                mob.visual.scale as f32 * zoom,
                 &mob.visual.color);
 
             // highlight selected item
-            if world.map.has_selection && 
-               layer_id == world.map.selected_layer &&
-               mob.uid == world.map.selected_item {
+            if world.map.is_selected(layer_id, mob.uid) {
                 
                 draw_texture(display, target, program,
                     BlendMode::Add,
                     tex_white,
                     tpos[0],
                     tpos[1], 
-                    (tile.size[0] * mob.visual.scale / 16.0) as f32, 
-                    (tile.size[1] * mob.visual.scale / 16.0) as f32, 
+                    (tile.size[0] * mob.visual.scale * camera.zoom / 16.0) as f32, 
+                    (tile.size[1] * mob.visual.scale * camera.zoom / 16.0) as f32, 
                     &[0.15, 0.2, 0.1, 1.0]);
             }
 
@@ -316,15 +368,15 @@ impl App {
             if tileset_id == 5 {
 
                 let glow_tile = &world.layer_tileset[2].tiles_by_id[&21]; // cloud set
-                let tpos = calc_tile_position(&mob.position, glow_tile.foot, 0.9, player_position, &window_center);
+                let tpos = calc_tile_position(&mob.position, glow_tile.foot, 0.9, camera, &window_center);
 
                 draw_texture(display, target, program,
                     BlendMode::Add,
                     &glow_tile.tex,
-                    tpos[0] - 170.0,
-                    tpos[1] - 50.0, 
-                    0.9, 
-                    0.9,
+                    tpos[0] - 170.0 * zoom,
+                    tpos[1] - 50.0 * zoom, 
+                    0.9 * zoom, 
+                    0.9 * zoom,
                     &mob.visual.glow);    
             }
 
@@ -339,11 +391,11 @@ impl App {
 
                         let set = mob.visual.particles.spawn_tile_set;
                         let tile = &world.layer_tileset[set].tiles_by_id.get(&p.tex_id).unwrap();
-                        let tpos = calc_tile_position(&mob.position, tile.foot, mob.visual.scale, player_position, &window_center);
+                        let tpos = calc_tile_position(&mob.position, tile.foot, mob.visual.scale, camera, &window_center);
 
                         // world coordinates to screen coordinates
-                        let xp = p.xpos as f32;
-                        let yp = ((p.ypos - p.zpos) * 0.5)  as f32;
+                        let xp = p.xpos as f32 * zoom;
+                        let yp = ((p.ypos - p.zpos) * 0.5)  as f32 * zoom;
 
                         let fade = quadratic_fade(p.age / p.lifetime);
 
@@ -352,8 +404,8 @@ impl App {
                             &tile.tex,
                             tpos[0] + xp,
                             tpos[1] + yp, 
-                            1.0, 
-                            1.0,
+                            zoom, 
+                            zoom,
                             &[p.color[0]*fade, p.color[1]*fade, p.color[2]*fade, 1.0])
                     }
                 }
@@ -362,29 +414,65 @@ impl App {
     }
 
     
-    fn move_player(&mut self, window_center: Vector2<f64>) {
-        
-        let screen_direction = vec2_sub(self.ui.context.mouse_state.position, window_center);
+    fn move_player(&mut self) {
         
-        // world coordinates have y components double as large
-        // as screen coordinates
-        let direction = [screen_direction[0], screen_direction[1] * 2.0];
+        let dest = screen_to_world_pos(&self.ui, &self.world.camera, &self.ui.context.mouse_state.position);
         
-        let distance = vec2_len(direction);
+        let map = &mut self.world.map;
+        let start = map.player_position();
+        let direction = vec2_sub(dest, start);
 
-        let player = self.world.map.layers[MAP_OBJECT_LAYER].get_mut(&self.world.map.player_id).unwrap();
-        let attributes = player.creature.as_ref().unwrap();
-        let time = distance / attributes.base_speed; // pixel per second
+        let path = map.find_path(start, dest);
 
-        player.move_time_left = time;
-        player.velocity = vec2_scale(direction, 1.0/time);
+        let player = map.layers[MAP_OBJECT_LAYER].get_mut(&map.player_id).unwrap();
+        let speed = player.creature.as_ref().unwrap().base_speed;
 
-        let dest = vec2_add(player.position, direction);
+        move_mob_along_path(player, path, speed);
 
-        let d = player.visual.orient(direction);
-        player.visual.current_image_id = player.visual.base_image_id + d;
+        println!("  moving {} pixels over {} seconds, destination is {:?}", vec2_len(direction), player.move_time_left, dest);        
+    }
+
+
+    /**
+     * Keeps the edited map, and puts the player where the editor looks at.
+     */
+    fn start_play(&mut self) {
+        let world = &mut self.world;
+        let map = &mut world.map;
 
-        println!("  moving {} pixels over {} seconds, destination is {:?}", distance, time, dest);        
+        match map.snapshot() {
+            Ok(snapshot) => {
+                self.controllers.play_start = Some((snapshot, world.camera));
+            },
+            Err(error) => {
+                println!("Error: cannot keep the map while playing, changes will stay: {}", error);
+            }
+        }
+
+        let player_id = map.player_id;
+        map.selection.clear();
+        map.set_object_position(MAP_OBJECT_LAYER, player_id, world.camera.position);
+        map.apply_to_mob(MAP_OBJECT_LAYER, player_id, |player| player.move_time_left = 0.0);
+    }
+
+
+    /**
+     * Puts the map and view back into the state before playing.
+     */
+    fn stop_play(&mut self) {
+        let world = &mut self.world;
+
+        if let Some((snapshot, camera)) = self.controllers.play_start.take() {
+            match world.map.restore(&snapshot, &mut world.rng) {
+                Ok(()) => {
+                    world.reload_map_textures();
+                    world.camera = camera;
+                },
+                Err(error) => {
+                    println!("Error: cannot restore the edited map: {}", error);
+                }
+            }
+        }
     }
 
 
@@ -394,18 +482,22 @@ impl App {
 
         println!("button event = {:?}", event);
 
-        if event.args.state == ButtonState::Release {
-            if event.args.button == Button::Keyboard(Key::Character("e".into())) {    
+        // while typing into a text field, the keys are no mode switches
+        let typing = self.ui.has_keyboard_focus() || self.controllers.current().has_keyboard_focus();
+
+        if event.args.state == ButtonState::Release && !typing {
+            if event.args.button == Button::Keyboard(Key::Character("e".into())) && !self.controllers.edit {    
+                self.stop_play();
                 self.controllers.edit = true;
                 println!("Switching to editor mode.");
             }
-            if event.args.button == Button::Keyboard(Key::Character("g".into())) {                        
+            if event.args.button == Button::Keyboard(Key::Character("g".into())) && self.controllers.edit {                        
+                self.start_play();
                 self.controllers.edit = false;
                 println!("Switching to game mode.");
             }        
         }
 
-        let window_center: Vector2<f64> = self.ui.window_center(); 
         let controller = &mut self.controllers.current();
         let world = &mut self.world;
         let ui = &mut self.ui;
@@ -414,7 +506,7 @@ impl App {
 
         if event.args.state == ButtonState::Release && !consumed {
             if event.args.button == Button::Mouse(MouseButton::Left) {
-                self.move_player(window_center);            
+                self.move_player();            
             }
         }
     }
@@ -455,39 +547,34 @@ pub fn read_lines(pathname: &str) -> Vec<String> {
 
 
 pub fn parse_rgba(color_str: &str) -> [f32; 4] {
+    try_parse_rgba(color_str).unwrap()
+}
+
+
+pub fn try_parse_rgba(color_str: &str) -> Option<[f32; 4]> {
     let mut color_iter = color_str.split(" ");
 
     let mut color: [f32; 4] = [0.0; 4];
     for i in 0..4 {
-        color[i] = color_iter.next().unwrap().parse::<f32>().unwrap();
+        color[i] = color_iter.next()?.parse::<f32>().ok()?;
     }
 
-    color
+    Some(color)
 }
 
 
-pub fn screen_to_world_pos(ui: &UI, player_pos: &Vector2<f64>, screen_pos: &Vector2<f64>) -> Vector2<f64>
+pub fn screen_to_world_pos(ui: &UI, camera: &Camera, screen_pos: &Vector2<f64>) -> Vector2<f64>
 {
-    let rel_mouse_x = screen_pos[0] - (ui.context.window_size[0]/2) as f64;
-    let rel_mouse_y = (screen_pos[1] - (ui.context.window_size[1]/2) as f64) * 2.0;
-
-    // transform to world coordinates
-    // it is relatrive to player position
-    let world_pos = [rel_mouse_x + player_pos[0], rel_mouse_y + player_pos[1]];
-
-    world_pos
+    camera.screen_to_world(screen_pos, &ui.window_center())
 }
 
 
-pub fn calc_tile_position(position: &Vector2<f64>, foot: Vector2<f64>, scale: f64, player_position: &Vector2<f64>, window_center: &Vector2<f64>) -> [f32; 2] {
+pub fn calc_tile_position(position: &Vector2<f64>, foot: Vector2<f64>, scale: f64, camera: &Camera, window_center: &Vector2<f64>) -> [f32; 2] {
         
-    let mut pos_x = position[0] - player_position[0];
-    let mut pos_y = (position[1] - player_position[1]) * 0.5;  
+    let screen_pos = camera.world_to_screen(position, window_center);
     
-    pos_x += window_center[0];
-    pos_y += window_center[1];
-    pos_x += -foot[0] * scale;
-    pos_y += -foot[1] * scale;
+    let pos_x = screen_pos[0] - foot[0] * scale * camera.zoom;
+    let pos_y = screen_pos[1] - foot[1] * scale * camera.zoom;
     
     [pos_x as f32, pos_y as f32]
 }
@@ -500,6 +587,27 @@ fn quadratic_fade(x: f64) -> f32 {
 
 fn main() {
     
+    // map conversion runs without opening a window
+    let args: Vec<String> = std::env::args().collect();
+    if (args.len() == 4 || args.len() == 5) && args[1] == "--convert-map" {
+        // an optional last argument "v10" writes the older format
+        let format = if args.get(4).is_some_and(|arg| arg == "v10") { MapFormat::V10 } else { MapFormat::Structured };
+
+        match map_file::convert_map(&args[2], &args[3], format) {
+            Ok(()) => println!("Converted maps/{} to maps/{}", args[2], args[3]),
+            Err(error) => println!("Could not convert map: {}", error),
+        }
+        return;
+    }
+
+    if args.len() == 4 && args[1] == "--import-server-map" {
+        match server_map_import::import_server_map(&args[2], &args[3]) {
+            Ok(()) => println!("Imported server map {} to maps/{}", args[2], args[3]),
+            Err(error) => println!("Could not import server map: {}", error),
+        }
+        return;
+    }
+
     let window_size = [1200, 770];
 
     // We start by creating the EventLoop, this can only be done once per process.
@@ -544,7 +652,11 @@ fn main() {
                     let button_event = ButtonEvent {
                         args: ButtonArgs {
                             state: if state == glium::winit::event::ElementState::Pressed { ButtonState::Press } else { ButtonState::Release },
-                            button: if button == glium::winit::event::MouseButton::Left {Button::Mouse(MouseButton::Left)} else {Button::Mouse(MouseButton::Right)},
+                            button: match button {
+                                glium::winit::event::MouseButton::Left => Button::Mouse(MouseButton::Left),
+                                glium::winit::event::MouseButton::Middle => Button::Mouse(MouseButton::Middle),
+                                _ => Button::Mouse(MouseButton::Right),
+                            },
                             scancode: None,
                         },
                         mx: app.ui.context.mouse_state.position[0],
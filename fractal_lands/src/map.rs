@@ -2,55 +2,99 @@ use vecmath::{Vector2, vec2_sub, vec2_add, vec2_scale, vec2_len, vec2_square_len
 use std::f64::consts::PI;
 
 use std::io::prelude::*;
-use std::io::{Result, BufWriter};
+use std::io::BufWriter;
 use std::fs::File;
+use std::fmt::Formatter;
 use std::path::PathBuf;
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::boxed::Box;
+use std::str::FromStr;
+use std::str::Lines;
 
 use rand::Rng;
 use rand::rngs::StdRng;
+use serde::{Serialize, Deserialize};
 
 use crate::item::Item;
 use crate::creature::Creature;
 use crate::creature::CreatureFactory;
 use crate::projectile::ProjectileBuilder;
 use crate::inventory::Inventory;
-use crate::particle_driver::ParticleDriver;
+use crate::particle_driver::{ParticleDriver, EmitterSettings};
 use crate::animation::*;
 use crate::sound::Sound;
 use crate::SoundPlayer;
 use crate::mob_group::MobGroup;
+use crate::mob_group::Population;
+use crate::spatial_index::SpatialIndex;
+use crate::collision::{Footprint, Blocker, MOVER_RADIUS, TILE_FOOTPRINT_FACTOR, MAX_BLOCKER_RADIUS, slide_move, clip_path};
+use crate::pathfinding::{find_path, NAV_MARGIN};
+use crate::ui::TileSet;
 use crate::CREATURE_TILESET;
-use crate::parse_rgba;
+use crate::TILESET_COUNT;
+use crate::try_parse_rgba;
 use crate::gl_support::BlendMode;
+use crate::map_file::{MapFileRef, MapFileLayerRef, MAP_FORMAT_VERSION, to_ron, from_ron};
+use crate::map_file::{serialize_rounded, serialize_rounded_vec2, serialize_rounded_color, format_number};
 
 
 pub const MAP_GROUND_LAYER:usize = 0;
 pub const MAP_OBJECT_LAYER:usize = 1;
 pub const MAP_CLOUD_LAYER:usize = 2;
 
+pub const MAP_LAYER_COUNT:usize = 7;
+
+// edge length of the spatial index cells, in world coordinates
+const INDEX_CELL_SIZE: f64 = 128.0;
+
 
 pub struct Map {
 
-    pub layers: [HashMap<u64, MapObject>; 7],
+    pub layers: [HashMap<u64, MapObject>; MAP_LAYER_COUNT],
+
+    // name, tileset and visibility of each layer
+    pub layer_info: [LayerInfo; MAP_LAYER_COUNT],
+
+    // editor flags of each layer. They are not saved, but kept for each
+    // map file which was loaded in this session
+    pub layer_flags: [LayerFlags; MAP_LAYER_COUNT],
+    session_layer_flags: HashMap<String, [LayerFlags; MAP_LAYER_COUNT]>,
+
+    // position lookup for each layer. Objects must be added, removed and moved 
+    // through the map methods so that the index stays in sync with the layers
+    indices: [SpatialIndex; MAP_LAYER_COUNT],
     pub animations: HashMap<u64, Box<dyn Animated>>,
     pub transitions: Vec<MapTransition>,
+
+    // a transition to a map which could not be loaded. It is not tried
+    // again until the player left it
+    failed_transition: Option<usize>,
     
     // 'AI' controlled objects
     pub mob_groups: Vec<MobGroup>,
 
+    // creature groups which are spawned when the map is loaded
+    pub populations: Vec<Population>,
+
     // all items on this map
     pub items: Inventory,
 
-    pub has_selection: bool,
-    pub selected_item: u64,
+    // uids of the selected objects, all of them are on the selected layer
+    pub selection: Vec<u64>,
     pub selected_layer: usize,
 
     pub name: String,
     pub map_image_name: String,
     pub backdrop_image_name: String,
 
+    // the file in the maps directory which this map was loaded from, empty for new maps
+    pub filename: String,
+
+    // blocking radius of each tile at scale 1.0, keyed by tileset and tile id
+    tile_footprints: HashMap<(usize, usize), f64>,
+
     pub factory: MapObjectFactory,
     pub creature_factory: CreatureFactory,
     pub projectile_builder: ProjectileBuilder,
@@ -86,25 +130,36 @@ impl Map {
         let mut player = factory.create_mob(39, 4, [1000.0, 1000.0], 24.0, 1.0);
         let player_id = player.uid;
         player.visual = player_visual;
+        player.mob_type = MobType::Player;
         player.update_action = UpdateAction::EmitDriveParticles;
         player.creature = Some(creature_factory.create("Player"));
+        let mut indices = std::array::from_fn(|_| SpatialIndex::new(INDEX_CELL_SIZE));
+        indices[MAP_OBJECT_LAYER].insert(player.uid, player.position);
         layers[MAP_OBJECT_LAYER].insert(player.uid, player);
 
         Map {
             layers,
+            layer_info: std::array::from_fn(LayerInfo::default_for),
+            layer_flags: Default::default(),
+            session_layer_flags: HashMap::new(),
+            indices,
 
             animations: HashMap::new(),
             transitions: Vec::new(),
+            failed_transition: None,
             mob_groups: Vec::new(),
+            populations: Vec::new(),
 
             items: Inventory::new(),
-            has_selection: false,
-            selected_item: 0,
+            selection: Vec::new(),
             selected_layer: 0,
 
             name: name.to_string(),
             map_image_name: map_image_name.to_string(),
             backdrop_image_name: backdrop_image_name.to_string(),
+            filename: String::new(),
+
+            tile_footprints: HashMap::new(),
         
             factory,
             creature_factory,
@@ -122,31 +177,123 @@ impl Map {
 
 
     pub fn find_nearest_object(&self, layer: usize, position: &Vector2<f64>, search_radius: f64, ignore_uid: u64) -> Option<u64> {
-        let objects = &self.layers[layer];
-        let mut distance = search_radius * search_radius;
-        let mut best_id = 0;
+        self.indices[layer].nearest(position, search_radius, ignore_uid)
+    }
+
+
+    pub fn find_objects_in_radius(&self, layer: usize, position: &Vector2<f64>, radius: f64) -> Vec<u64> {
+        self.indices[layer].in_radius(position, radius)
+    }
+
+
+    pub fn find_objects_in_rect(&self, layer: usize, corner_1: &Vector2<f64>, corner_2: &Vector2<f64>) -> Vec<u64> {
+        self.indices[layer].in_rect(corner_1, corner_2)
+    }
+
+
+    pub fn add_object(&mut self, layer: usize, mob: MapObject) {
+        self.indices[layer].insert(mob.uid, mob.position);
+        self.layers[layer].insert(mob.uid, mob);
+    }
+
+
+    pub fn remove_object(&mut self, layer: usize, uid: u64) -> Option<MapObject> {
+        self.indices[layer].remove(uid);
+        self.layers[layer].remove(&uid)
+    }
 
-        for (_key, object) in objects {
-            let dx = object.position[0] - position[0];
-            let dy = object.position[1] - position[1];
-            let d2 = dx * dx + dy * dy;
 
-            // println!("object {} has distance {}", object.uid, d2);
+    pub fn set_object_position(&mut self, layer: usize, uid: u64, position: Vector2<f64>) {
+        if let Some(mob) = self.layers[layer].get_mut(&uid) {
+            mob.position = position;
+            self.indices[layer].insert(uid, position);
+        }
+    }
+
+
+    /**
+     * Derives the default footprints of map objects from the tile sizes
+     */
+    pub fn set_tile_footprints(&mut self, tilesets: &[TileSet]) {
+        self.tile_footprints.clear();
 
-            if d2 < distance && object.uid != ignore_uid {
-                distance = d2;
-                best_id = object.uid;
+        for (tileset_id, set) in tilesets.iter().enumerate() {
+            for (tile_id, tile) in &set.tiles_by_id {
+                self.tile_footprints.insert((tileset_id, *tile_id), tile.size[0] * TILE_FOOTPRINT_FACTOR);
             }
         }
+    }
+
+
+    /**
+     * Without tilesets, e.g. in tests, all tiles are taken as existing
+     */
+    fn tile_exists(&self, tileset_id: usize, tile_id: usize) -> bool {
+        self.tile_footprints.is_empty() || self.tile_footprints.contains_key(&(tileset_id, tile_id))
+    }
+
+
+    fn check_emitter(&self, emitter: &EmitterSettings) -> Result<(), String> {
+        if emitter.tileset_id >= TILESET_COUNT {
+            return Err(format!("unknown particle tileset {}", emitter.tileset_id));
+        }
+
+        match emitter.spawn_ids.iter().find(|id| !self.tile_exists(emitter.tileset_id, **id)) {
+            Some(id) => Err(format!("tileset {} has no particle tile {}", emitter.tileset_id, id)),
+            None => Ok(()),
+        }
+    }
 
-        let mut result:Option<u64> = None;
 
-        if distance < search_radius * search_radius {
-            result = Some(best_id);
-            // println!("  best object is {}", best_id);
+    /**
+     * @return the radius in which this object blocks movement, 0 if it does not block
+     */
+    pub fn blocking_radius(&self, mob: &MapObject) -> f64 {
+        if mob.mob_type != MobType::MapObject || mob.uid == self.player_id {
+            return 0.0;
         }
 
-        result
+        let radius = 
+            match mob.footprint {
+                Footprint::Radius(radius) => radius,
+                Footprint::FromTile => {
+                    let key = (mob.visual.tileset_id, mob.visual.base_image_id);
+                    self.tile_footprints.get(&key).unwrap_or(&0.0) * mob.visual.scale
+                }
+            };
+
+        radius.clamp(0.0, MAX_BLOCKER_RADIUS)
+    }
+
+
+    /**
+     * @return waypoints which lead from start to destination around the obstacles
+     */
+    pub fn find_path(&self, from: Vector2<f64>, to: Vector2<f64>) -> Vec<Vector2<f64>> {
+        let middle = vec2_scale(vec2_add(from, to), 0.5);
+        let blockers = self.blockers_near(&middle, vec2_len(vec2_sub(to, from)) * 0.5 + NAV_MARGIN);
+
+        find_path(from, to, MOVER_RADIUS, &blockers)
+    }
+
+
+    /**
+     * @return the obstacles which could be touched by a mover within the given range
+     */
+    pub fn blockers_near(&self, position: &Vector2<f64>, range: f64) -> Vec<Blocker> {
+        let search_radius = range + MOVER_RADIUS + MAX_BLOCKER_RADIUS;
+        let mut blockers = Vec::new();
+
+        for uid in self.find_objects_in_radius(MAP_OBJECT_LAYER, position, search_radius) {
+            let mob = &self.layers[MAP_OBJECT_LAYER][&uid];
+            let radius = self.blocking_radius(mob);
+
+            if radius > 0.0 {
+                blockers.push(Blocker { position: mob.position, radius });
+            }
+        }
+
+        blockers
     }
 
 
@@ -156,22 +303,45 @@ impl Map {
         let mut phit_list = Vec::new();
 
         {
+            // creatures stay close to their group center, obstacles around it are all they need to know about
+            let group_blockers: Vec<Vec<Blocker>> = 
+                self.mob_groups.iter().map(|group| self.blockers_near(&group.center, 400.0)).collect();
+
             let groups = &mut self.mob_groups;
             let mobs = &mut self.layers[MAP_OBJECT_LAYER];
             let factory = &mut self.factory;
             let projectile_builder = &mut self.projectile_builder;
 
-            for group in groups {
-                group.update(self.player_id, dt, mobs, rng, factory, projectile_builder, speaker);
+            for (group, blockers) in groups.iter_mut().zip(group_blockers.iter()) {
+                group.update(self.player_id, dt, mobs, rng, factory, projectile_builder, speaker, blockers);
             }
         }
 
+        // ships and creatures must not move through obstacles
+        let mut move_blockers = HashMap::new();
+
+        for (uid, mob) in &self.layers[MAP_OBJECT_LAYER] {
+            if mob.move_time_left > 0.0 && (mob.mob_type == MobType::Player || mob.mob_type == MobType::Creature) {
+                let step = vec2_len(mob.velocity) * dt;
+                move_blockers.insert(*uid, self.blockers_near(&mob.position, step));
+            }
+        }
 
         for (_key, mob) in &mut self.layers[MAP_OBJECT_LAYER] {
             let before = mob.move_time_left;
-            mob.move_dt(dt);
+            let blockers = move_blockers.get(&mob.uid).map(|b| b.as_slice()).unwrap_or(&[]);
+            mob.move_dt(dt, blockers);
+
+            // reached a waypoint, go on to the next
+            if before > 0.0 && mob.move_time_left <= 0.0 {
+                follow_path(mob);
+            }
+
             let after = mob.move_time_left;
 
+            // this also picks up projectiles which were fired by the mob groups
+            self.indices[MAP_OBJECT_LAYER].insert(mob.uid, mob.position);
+
             // did the move just end?
             if before > 0.0 && after <= 0.0 {
                 mob.visual.particles.clear();
@@ -184,18 +354,7 @@ impl Map {
             // particle stuff
             {
                 let particles = &mut mob.visual.particles;
-                let len = particles.spawn_ids.len();
-
-                if len > 0 {
-                    let chance = particles.spawn_chance * dt;
-                    if rng.gen::<f64>() < chance {
-                        let spark = particles.spawn_ids[rng.gen_range(0..len)];
-                        
-                        particles.add_particle(0.0, -400.0, 0.0, 0.0, 0.0, 0.0, 
-                                               0.1, spark, [0.7, 0.75, 0.9]);
-                    }
-                }
-
+                particles.emit(dt, rng);
                 particles.drive(dt);
             }
 
@@ -248,30 +407,61 @@ impl Map {
         }
 
         for id in kill_list {
-            self.layers[MAP_OBJECT_LAYER].remove(&id);
+            self.remove_object(MAP_OBJECT_LAYER, id);
             self.animations.remove(&id);
         }
     }
 
 
+    /**
+     * @return the index of the transition whose catchment area contains the position.
+     *         If there are several, the one with the closest entrance is chosen.
+     */
+    pub fn find_transition(&self, position: &Vector2<f64>) -> Option<usize> {
+        let mut best = None;
+        let mut best_distance = f64::MAX;
+
+        for (index, transit) in self.transitions.iter().enumerate() {
+            let d = vec2_square_len(vec2_sub(*position, transit.from));
+            if d < transit.rad * transit.rad && d < best_distance {
+                best = Some(index);
+                best_distance = d;
+            }
+        }
+
+        best
+    }
+
+
     pub fn check_player_transition(&mut self, rng: &mut StdRng) -> bool {
         let player = self.layers[MAP_OBJECT_LAYER].get(&self.player_id).unwrap();
         let pos = player.position;
-        let mut best_map = -1;
-        
-        for transit in &self.transitions {
-            let v = vec2_sub(pos, transit.from);
-            let d = vec2_square_len(v);
-            if d < transit.rad * transit.rad {
-                best_map = transit.to;
-            }
+        let best_transit = self.find_transition(&pos);
+
+        if best_transit == self.failed_transition {
+            return false;
         }
 
-        if best_map >= 0 {
-            self.load("warmup.map");
-            self.populate("warmup.csv", rng);
+        self.failed_transition = None;
 
-            return true;
+        if let Some(index) = best_transit {
+            let transit = self.transitions[index].clone();
+
+            match self.load(&transit.to_map, rng) {
+                Ok(()) => {
+                    self.set_object_position(MAP_OBJECT_LAYER, self.player_id, transit.to_position);
+
+                    let player = self.layers[MAP_OBJECT_LAYER].get_mut(&self.player_id).unwrap();
+                    let visual = &mut player.visual;
+                    visual.current_image_id = visual.base_image_id + transit.facing % visual.directions;
+
+                    return true;
+                },
+                Err(error) => {
+                    println!("Error: cannot enter map: {}", error);
+                    self.failed_transition = Some(index);
+                }
+            }
         }
 
         false
@@ -345,16 +535,206 @@ impl Map {
     }
 
 
-    pub fn populate(&mut self, _filename: &str, rng: &mut StdRng) {
+    /**
+     * Spawns the creature groups of all populations of this map.
+     */
+    pub fn populate(&mut self, rng: &mut StdRng) {
+
+        let populations = self.populations.clone();
+
+        for population in populations {
+            let group = self.make_creature_group(&population, rng);
+            self.mob_groups.push(group);
+        }
+    }
+
+
+    /**
+     * Loads a map file from the maps directory. If anything goes wrong, the
+     * current map is left untouched and an error describing the file, line
+     * and field is returned.
+     */
+    pub fn load(&mut self, filename: &str, rng: &mut StdRng) -> Result<(), MapLoadError> {
+
+        let mut path = PathBuf::new();
+        path.push("maps");
+        path.push(filename);
+
+        let content = std::fs::read_to_string(path.as_path())
+            .map_err(|error| MapLoadError::Io { file: filename.to_string(), error })?;
+
+        self.load_content(filename, &content, rng)
+    }
+
+
+    /**
+     * Like load(), but with the content of the map file already at hand
+     */
+    pub(crate) fn load_content(&mut self, filename: &str, content: &str, rng: &mut StdRng) -> Result<(), MapLoadError> {
+
+        // parse everything first, the map is only changed once the whole file was read
+        let data = 
+            if content.starts_with("v10") {
+                self.parse_v10(filename, content)?
+            }
+            else {
+                self.parse_structured(filename, content)?
+            };
+
+        self.apply_map_data(data, rng);
+
+        self.session_layer_flags.insert(self.filename.to_string(), self.layer_flags);
+        self.layer_flags = self.session_layer_flags.get(filename).copied().unwrap_or_default();
+        self.filename = filename.to_string();
+
+        Ok(())
+    }
+
+
+    /**
+     * Reads the older, line oriented map format
+     */
+    fn parse_v10(&mut self, filename: &str, content: &str) -> Result<MapData, MapLoadError> {
+
+        let mut reader = MapReader::new(filename, content);
+
+        reader.next_line("version")?;
+        reader.expect_marker("begin map header")?;
+        let name = reader.next_line("map name")?.to_string();
+        let map_image_name = reader.next_line("map image name")?.to_string();
+        let backdrop_image_name = reader.next_line("backdrop image name")?.to_string();
+        println!("map name={} image={} backdrop={}", name, map_image_name, backdrop_image_name);
+
+        // older maps have no layer descriptions
+        let mut layer_info: [LayerInfo; MAP_LAYER_COUNT] = std::array::from_fn(LayerInfo::default_for);
+        let line = reader.next_line("end map header")?;
+
+        if line != "end map header" {
+            let parts: Vec<&str> = line.split(",").collect();
+            if reader.field(&parts, 0, "layers")? != "layers" {
+                return Err(reader.unexpected_line("layers", line));
+            }
+
+            let count = reader.parse_field::<usize>(&parts, 1, "layer count")?;
+            if count > MAP_LAYER_COUNT {
+                return Err(reader.invalid_field("layer count", parts[1]));
+            }
+
+            for info in layer_info.iter_mut().take(count) {
+                let line = reader.next_line("layer description")?;
+                *info = load_layer_info(&reader, line)?;
+            }
+
+            reader.expect_marker("end map header")?;
+        }
+
+        let mut objects = Vec::new();
+        let mut transitions = Vec::new();
+        let mut populations = Vec::new();
+
+        // the remaining sections are optional and may come in any order
+        while let Some(line) = reader.next_optional_line() {
+            match line {
+                "begin map objects" => {
+                    reader.read_section("end map objects", |reader, line| {
+                        objects.push(self.load_mob(reader, line, &layer_info)?);
+                        Ok(())
+                    })?;
+                },
+                "begin map transitions" => {
+                    reader.read_section("end map transitions", |reader, line| {
+                        transitions.push(self.load_transition(reader, line)?);
+                        Ok(())
+                    })?;
+                },
+                "begin map populations" => {
+                    reader.read_section("end map populations", |reader, line| {
+                        populations.push(self.load_population(reader, line)?);
+                        Ok(())
+                    })?;
+                },
+                "" => {
+                    // blank lines between sections are fine
+                },
+                _ => {
+                    return Err(reader.unexpected_line("begin map section", line));
+                }
+            }
+        }
+
+        Ok(MapData {
+            name,
+            map_image_name,
+            backdrop_image_name,
+            layer_info,
+            objects,
+            transitions,
+            populations,
+        })
+    }
+
+
+    /**
+     * Reads the structured map format
+     */
+    fn parse_structured(&self, filename: &str, content: &str) -> Result<MapData, MapLoadError> {
+
+        let map_file = from_ron(content).map_err(|error| MapLoadError::Format {
+            file: filename.to_string(),
+            line: error.position.line,
+            message: error.code.to_string(),
+        })?;
+
+        let format_error = |message: String| MapLoadError::Format { file: filename.to_string(), line: 0, message };
+
+        if map_file.version > MAP_FORMAT_VERSION {
+            return Err(format_error(format!("map format version {} is not supported", map_file.version)));
+        }
+
+        if map_file.layers.len() > MAP_LAYER_COUNT {
+            return Err(format_error(format!("too many layers: {}", map_file.layers.len())));
+        }
+
+        for population in &map_file.populations {
+            if !self.creature_factory.contains(&population.creature_id) {
+                return Err(format_error(format!("unknown creature '{}'", population.creature_id)));
+            }
+        }
+
+        let mut layer_info: [LayerInfo; MAP_LAYER_COUNT] = std::array::from_fn(LayerInfo::default_for);
+        let mut objects = Vec::new();
+
+        for (layer, file_layer) in map_file.layers.into_iter().enumerate() {
+            if file_layer.info.tileset_id >= TILESET_COUNT {
+                return Err(format_error(format!("layer {} has an unknown tileset {}", layer, file_layer.info.tileset_id)));
+            }
+
+            layer_info[layer] = file_layer.info;
+
+            for mob in file_layer.objects {
+                if mob.visual.directions == 0 {
+                    return Err(format_error(format!("object {} has no directions", mob.uid)));
+                }
 
-        let position = [1216.0, 1448.0];
+                objects.push((layer, mob));
+            }
+        }
 
-        let group = self.make_creature_group("Targetting Drone", 5, 9, position, 40.0, rng);
-        self.mob_groups.push(group);
+        Ok(MapData {
+            name: map_file.name,
+            map_image_name: map_file.map_image_name,
+            backdrop_image_name: map_file.backdrop_image_name,
+            layer_info,
+            objects,
+            transitions: map_file.transitions,
+            populations: map_file.populations,
+        })
     }
 
 
-    pub fn load(&mut self, filename: &str) {
+    pub(crate) fn apply_map_data(&mut self, data: MapData, rng: &mut StdRng) {
+
+        let MapData { name, map_image_name, backdrop_image_name, layer_info, objects, transitions, populations } = data;
 
         // preserve player
         let mut player = self.layers[MAP_OBJECT_LAYER].remove(&self.player_id).unwrap();
@@ -362,230 +742,506 @@ impl Map {
         for layer in &mut self.layers {
             layer.clear();
         }
-        self.transitions.clear();
-
-        let mut path = PathBuf::new();
-        path.push("maps");
-        path.push(filename);
+        for index in &mut self.indices {
+            index.clear();
+        }
+        self.animations.clear();
+        self.mob_groups.clear();
+        self.failed_transition = None;
 
-        let content = std::fs::read_to_string(path.as_path()).unwrap();
-        let mut lines = content.lines();
+        // ids from the file are kept, so they stay stable over save and load
+        for (_layer, mob) in &objects {
+            self.factory.reserve_id(mob.uid);
+        }
 
-        lines.next(); // version
+        let mut used_ids = HashSet::new();
+        used_ids.insert(self.player_id);
 
-        lines.next(); // header start
-        self.name = lines.next().unwrap().to_string();
-        self.map_image_name = lines.next().unwrap().to_string();
-        self.backdrop_image_name = lines.next().unwrap().to_string();
-        println!("map name={} image={} backdrop={}", self.name, self.map_image_name, self.backdrop_image_name);
-        lines.next(); // header end
+        for (layer, mut mob) in objects {
+            // emitters which would spawn unknown tiles are dropped, they would break rendering
+            if let Some(Err(message)) = mob.visual.particles.emitter().map(|emitter| self.check_emitter(emitter)) {
+                println!("Warning: object {}: {}, the particle emitter is removed", mob.uid, message);
+                mob.visual.particles.set_emitter(None);
+            }
 
-        lines.next(); // objects start
-        let mut line = lines.next().unwrap();
+            if !used_ids.insert(mob.uid) {
+                // duplicate, or a map which was made before ids were saved
+                mob.uid = self.factory.next_uid();
+                used_ids.insert(mob.uid);
+            }
 
-        let object_end_marker = "end map objects".to_string();
-        while object_end_marker != line {
-            println!("line='{}'", line);
-            self.load_mob(line);
-            line = lines.next().unwrap();
+            self.add_object(layer, mob);
         }
 
-        lines.next(); // transitions start
-        line = lines.next().unwrap();
-
-        let transition_end_marker = "end map transitions".to_string();
-        while transition_end_marker != line {
-            println!("line='{}'", line);
-            self.load_transition(line);
-            line = lines.next().unwrap();
-        }
+        self.layer_info = layer_info;
+        self.transitions = transitions;
+        self.populations = populations;
+        self.name = name;
+        self.map_image_name = map_image_name;
+        self.backdrop_image_name = backdrop_image_name;
+        self.selection.clear();
 
         println!("player_id={}", self.player_id);
 
         // stop player movement
         player.move_time_left = 0.0;
-        self.layers[MAP_OBJECT_LAYER].insert(self.player_id, player);
+        self.add_object(MAP_OBJECT_LAYER, player);
+
+        self.populate(rng);
     }
 
 
-    fn load_mob(&mut self, line: &str) {
+    fn load_mob(&mut self, reader: &MapReader, line: &str, layer_info: &[LayerInfo]) -> Result<(usize, MapObject), MapLoadError> {
         let parts: Vec<&str> = line.split(",").collect();
 
-        let layer = parts[0].parse::<usize>().unwrap();
-        let tile_id = parts[1].parse::<usize>().unwrap();
-        let directions = parts[2].parse::<usize>().unwrap();
+        let layer = reader.parse_field::<usize>(&parts, 0, "layer")?;
+        let tile_id = reader.parse_field::<usize>(&parts, 1, "tile id")?;
+        let directions = reader.parse_field::<usize>(&parts, 2, "directions")?;
 
-        let x = parts[3].parse::<f64>().unwrap();
-        let y = parts[4].parse::<f64>().unwrap();
-        let height = parts[5].parse::<f64>().unwrap();
-        let scale = parts[6].parse::<f64>().unwrap();
+        // the facing of an object is counted modulo the directions
+        if directions == 0 {
+            return Err(reader.invalid_field("directions", parts[2]));
+        }
+
+        let x = reader.parse_field::<f64>(&parts, 3, "x")?;
+        let y = reader.parse_field::<f64>(&parts, 4, "y")?;
+        let height = reader.parse_field::<f64>(&parts, 5, "height")?;
+        let scale = reader.parse_field::<f64>(&parts, 6, "scale")?;
 
         // parts[7] is an RGBA tuple
-        let color = parse_rgba(parts[7]);
-        let blend = key_to_blend(parts[8]);
+        let color_str = reader.field(&parts, 7, "color")?;
+        let color = try_parse_rgba(color_str).ok_or_else(|| reader.invalid_field("color", color_str))?;
+        let blend = key_to_blend(reader.field(&parts, 8, "blend")?);
+
+        // older maps have no footprints
+        let footprint = 
+            match parts.get(9).map(|p| p.trim()) {
+                None | Some("auto") => Footprint::FromTile,
+                Some(_) => Footprint::Radius(reader.parse_field::<f64>(&parts, 9, "footprint")?),
+            };
+
+        // older maps have no object ids either, 0 marks them to get a new one
+        let uid = 
+            match parts.get(10) {
+                None => 0,
+                Some(_) => reader.parse_field::<u64>(&parts, 10, "uid")?,
+            };
+
+        if layer >= self.layers.len() {
+            return Err(reader.invalid_field("layer", parts[0]));
+        }
 
         println!("{}, {}, {}, {}, {}, {}, {:?}, {:?}", layer, tile_id, x, y, height, scale, color, blend);
 
-        let mut mob = self.factory.create_mob(tile_id, layer, [x, y], height, scale);
+        let mut mob = self.factory.create_mob(tile_id, layer_info[layer].tileset_id, [x, y], height, scale);
         mob.visual.color = color;
         mob.visual.blend = blend;
         mob.visual.directions = directions;
+        mob.footprint = footprint;
 
-        self.layers[layer].insert(mob.uid, mob);
+        if uid != 0 {
+            mob.uid = uid;
+        }
+
+        Ok((layer, mob))
     }
 
 
-    fn load_transition(&mut self, line: &str) {
-        let mut parts = line.split(",");
+    fn load_transition(&mut self, reader: &MapReader, line: &str) -> Result<MapTransition, MapLoadError> {
+        let parts: Vec<&str> = line.split(",").collect();
+
+        let x = reader.parse_field::<f64>(&parts, 0, "x")?;
+        let y = reader.parse_field::<f64>(&parts, 1, "y")?;
+        let r = reader.parse_field::<f64>(&parts, 2, "radius")?;
+        let to_map = reader.field(&parts, 3, "destination map")?.trim();
+        let to_x = reader.parse_field::<f64>(&parts, 4, "destination x")?;
+        let to_y = reader.parse_field::<f64>(&parts, 5, "destination y")?;
+        let facing = reader.parse_field::<usize>(&parts, 6, "facing")?;
 
-        let x = parts.next().unwrap().parse::<f64>().unwrap();
-        let y = parts.next().unwrap().parse::<f64>().unwrap();
-        let r = parts.next().unwrap().parse::<f64>().unwrap();
-        let map_id = parts.next().unwrap().parse::<i32>().unwrap();
+        if to_map.is_empty() {
+            return Err(reader.invalid_field("destination map", to_map));
+        }
 
-        self.transitions.push(MapTransition {
+        Ok(MapTransition {
             from: [x, y],
             rad: r,
-            to: map_id,
-        });
+            to_map: to_map.to_string(),
+            to_position: [to_x, to_y],
+            facing,
+        })
+    }
+
+
+    fn load_population(&mut self, reader: &MapReader, line: &str) -> Result<Population, MapLoadError> {
+        let parts: Vec<&str> = line.split(",").collect();
+
+        let creature_id = reader.field(&parts, 0, "creature id")?.trim();
+        let min_count = reader.parse_field::<i32>(&parts, 1, "min count")?;
+        let max_count = reader.parse_field::<i32>(&parts, 2, "max count")?;
+        let x = reader.parse_field::<f64>(&parts, 3, "x")?;
+        let y = reader.parse_field::<f64>(&parts, 4, "y")?;
+        let spacing = reader.parse_field::<f64>(&parts, 5, "spacing")?;
+        let mobile = reader.parse_field::<bool>(&parts, 6, "mobile")?;
+
+        if !self.creature_factory.contains(creature_id) {
+            return Err(reader.invalid_field("creature id", creature_id));
+        }
+
+        if min_count < 0 || max_count < min_count {
+            return Err(reader.invalid_field("max count", parts[2]));
+        }
+
+        Ok(Population {
+            creature_id: creature_id.to_string(),
+            min_count,
+            max_count,
+            center: [x, y],
+            spacing,
+            mobile,
+        })
     }
 
 
-    pub fn save(&self, filename: &str) -> Result<()> {
+    pub fn save(&self, filename: &str) -> std::io::Result<()> {
         let mut path = PathBuf::new();
         path.push("maps");
         path.push(filename);
 
+        let content = self.to_file_content().map_err(std::io::Error::other)?;
+
         let f = File::create(path.as_path())?;
-        {        
-            let mut writer = BufWriter::new(f);
+        let mut writer = BufWriter::new(f);
+        writer.write_all(content.as_bytes())?;
+        writer.write_all("\n".as_bytes())?;
 
-            writer.write("v10\n".as_bytes())?;
-            
-            writer.write("begin map header\n".as_bytes())?;
-            let name = self.name.to_string()  + "\n";
-            writer.write(name.as_bytes())?;
-            let map_image_name = self.map_image_name.to_string() + "\n";
-            writer.write(map_image_name.as_bytes())?;
-            let backdrop_image_name = self.backdrop_image_name.to_string() + "\n";
-            writer.write(backdrop_image_name.as_bytes())?;
-            writer.write("end map header\n".as_bytes())?;
+        Ok(())
+    }
+
+
+    /**
+     * @return the map in the structured map file format
+     */
+    pub(crate) fn to_file_content(&self) -> Result<String, ron::Error> {
+        let mut layers = Vec::new();
+
+        for (info, objects) in self.layer_info.iter().zip(self.layers.iter()) {
+
+            // creatures and projectiles are spawned at runtime, only the map decorations are kept
+            let mut objects: Vec<&MapObject> = objects.values()
+                .filter(|object| object.uid != self.player_id && object.mob_type == MobType::MapObject)
+                .collect();
+
+            // a fixed order keeps the files diffable
+            objects.sort_by_key(|object| object.uid);
+
+            layers.push(MapFileLayerRef { info, objects });
+        }
+
+        let map_file = MapFileRef {
+            version: MAP_FORMAT_VERSION,
+            name: &self.name,
+            map_image_name: &self.map_image_name,
+            backdrop_image_name: &self.backdrop_image_name,
+            layers,
+            transitions: &self.transitions,
+            populations: &self.populations,
+        };
+
+        to_ron(&map_file)
+    }
+
+
+    /**
+     * Writes the map in the older, line oriented v10 format. It has no room for
+     * particle emitters, glow colors or per-object tilesets, these are left out.
+     */
+    pub fn save_v10(&self, filename: &str) -> std::io::Result<()> {
+        let mut path = PathBuf::new();
+        path.push("maps");
+        path.push(filename);
+
+        let f = File::create(path.as_path())?;
+        let mut writer = BufWriter::new(f);
+
+        self.write_v10(&mut writer)?;
+        writer.flush()
+    }
+
+
+    /**
+     * Writes the map in the v10 format to any writer, e.g. a file or a buffer
+     */
+    pub(crate) fn write_v10(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        writer.write_all("v10\n".as_bytes())?;
+        
+        writer.write_all("begin map header\n".as_bytes())?;
+        let name = self.name.to_string()  + "\n";
+        writer.write_all(name.as_bytes())?;
+        let map_image_name = self.map_image_name.to_string() + "\n";
+        writer.write_all(map_image_name.as_bytes())?;
+        let backdrop_image_name = self.backdrop_image_name.to_string() + "\n";
+        writer.write_all(backdrop_image_name.as_bytes())?;
+
+        let layers = "layers,".to_string() + &self.layer_info.len().to_string() + "\n";
+        writer.write_all(layers.as_bytes())?;
+
+        for info in &self.layer_info {
+            let line = 
+                info.name.to_string() + "," +
+                &info.tileset_id.to_string() + "," +
+                &info.visible.to_string() + "\n";
+            writer.write_all(line.as_bytes())?;
+        }
 
-            writer.write("begin map objects\n".as_bytes())?;
-            self.save_layer(&mut writer, MAP_GROUND_LAYER)?;
-            self.save_layer(&mut writer, MAP_OBJECT_LAYER)?;
-            self.save_layer(&mut writer, MAP_CLOUD_LAYER)?;
-            writer.write("end map objects\n".as_bytes())?;
+        writer.write_all("end map header\n".as_bytes())?;
 
-            self.save_map_transitions(&mut writer)?
+        writer.write_all("begin map objects\n".as_bytes())?;
+        for layer in 0..self.layers.len() {
+            self.save_layer(writer, layer)?;
         }
+        writer.write_all("end map objects\n".as_bytes())?;
+
+        self.save_map_transitions(writer)?;
+        self.save_populations(writer)?;
 
         Ok(())
     }
     
     
-    fn save_layer(&self, writer: &mut BufWriter<File>, layer: usize) -> Result<()> {
-        let objects = &self.layers[layer];
+    fn save_layer(&self, writer: &mut impl Write, layer: usize) -> std::io::Result<()> {
 
-        for (_key, object) in objects {
+        // creatures and projectiles are spawned at runtime, only the map decorations are kept
+        let mut objects: Vec<&MapObject> = self.layers[layer].values()
+            .filter(|object| object.uid != self.player_id && object.mob_type == MobType::MapObject)
+            .collect();
 
-            if object.uid != self.player_id {
+        // a fixed order keeps the files diffable
+        objects.sort_by_key(|object| object.uid);
 
-                let color = object.visual.color; 
+        for object in objects {
+            let color = object.visual.color; 
 
-                let line = 
+            let line = 
                 layer.to_string() + "," +
                 &object.visual.base_image_id.to_string() + "," +
                 &object.visual.directions.to_string() + "," +
-                &object.position[0].to_string() + "," +
-                &object.position[1].to_string() + "," +
-                &object.visual.height.to_string() + "," +
-                &object.visual.scale.to_string() + "," +
-                &color[0].to_string() + " " +
-                &color[1].to_string() + " " +
-                &color[2].to_string() + " " +
-                &color[3].to_string() + "," +            
-                &blend_to_key(&object.visual.blend) +
+                &format_number(object.position[0]) + "," +
+                &format_number(object.position[1]) + "," +
+                &format_number(object.visual.height) + "," +
+                &format_number(object.visual.scale) + "," +
+                &format_number(color[0] as f64) + " " +
+                &format_number(color[1] as f64) + " " +
+                &format_number(color[2] as f64) + " " +
+                &format_number(color[3] as f64) + "," +            
+                blend_to_key(&object.visual.blend) + "," +
+                &footprint_to_key(&object.footprint) + "," +
+                &object.uid.to_string() +
                 "\n";
-                
-                writer.write(line.as_bytes())?;
-            }
+            
+            writer.write_all(line.as_bytes())?;
         }
 
         Ok(())
     }
 
     
-    fn save_map_transitions(&self, writer: &mut BufWriter<File>) -> Result<()> {
-        writer.write("begin map transitions\n".as_bytes())?;
+    fn save_map_transitions(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        writer.write_all("begin map transitions\n".as_bytes())?;
 
         for transit in &self.transitions {
             let line = 
-                transit.from[0].to_string() + "," +
-                &transit.from[1].to_string() + "," +
-                &transit.rad.to_string() + "," +
-                &transit.to.to_string() + "\n";
-            writer.write(line.as_bytes())?;
+                format_number(transit.from[0]) + "," +
+                &format_number(transit.from[1]) + "," +
+                &format_number(transit.rad) + "," +
+                &transit.to_map + "," +
+                &format_number(transit.to_position[0]) + "," +
+                &format_number(transit.to_position[1]) + "," +
+                &transit.facing.to_string() + "\n";
+            writer.write_all(line.as_bytes())?;
         }
 
-        writer.write("end map transitions\n".as_bytes())?;
+        writer.write_all("end map transitions\n".as_bytes())?;
 
         Ok(())
     }
 
     
-    pub fn move_selected_object(&mut self, dx: f64, dy: f64) {        
-        if self.has_selection {
-            let object = self.layers[self.selected_layer].get_mut(&self.selected_item).unwrap();
-            object.position[0] += dx;
-            object.position[1] += dy;
+    fn save_populations(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        writer.write_all("begin map populations\n".as_bytes())?;
+
+        for population in &self.populations {
+            let line = 
+                population.creature_id.to_string() + "," +
+                &population.min_count.to_string() + "," +
+                &population.max_count.to_string() + "," +
+                &format_number(population.center[0]) + "," +
+                &format_number(population.center[1]) + "," +
+                &format_number(population.spacing) + "," +
+                &population.mobile.to_string() + "\n";
+            writer.write_all(line.as_bytes())?;
         }
+
+        writer.write_all("end map populations\n".as_bytes())?;
+
+        Ok(())
     }
 
-    pub fn apply_to_selected_mob<F>(&mut self, func: F)
-        where F: FnOnce(&mut MapObject) {
-        let mob = self.layers[self.selected_layer].get_mut(&self.selected_item);
 
-        match mob {
-            None => {}
-            Some(mob) => { func(mob); }
-        }
+    /**
+     * Keeps everything which the editor can change, so that the map can be
+     * restored after it was played.
+     */
+    pub fn snapshot(&self) -> Result<MapSnapshot, ron::Error> {
+        Ok(MapSnapshot {
+            content: self.to_file_content()?,
+            filename: self.filename.to_string(),
+            layer_flags: self.layer_flags,
+            selected_layer: self.selected_layer,
+            selection: self.selection.clone(),
+            player_position: self.player_position(),
+        })
     }
 
 
-    pub fn make_creatures(&mut self, id: &str, min_count: i32, max_count: i32, center: Vector2<f64>, spacing: f64, scale: f64, rng: &mut StdRng) -> Vec<MapObject> {
+    /**
+     * Puts the map back into the state of the snapshot. Creatures are spawned
+     * anew from the populations.
+     */
+    pub fn restore(&mut self, snapshot: &MapSnapshot, rng: &mut StdRng) -> Result<(), MapLoadError> {
+        let data = self.parse_structured(&snapshot.filename, &snapshot.content)?;
 
-        let count = rng.gen_range(min_count ..= max_count) as usize;
+        self.apply_map_data(data, rng);
 
-        let mut list: Vec<MapObject> = Vec::with_capacity(count);
-    
-        for _i in 0 .. count {
+        self.filename = snapshot.filename.to_string();
+        self.layer_flags = snapshot.layer_flags;
+        self.selected_layer = snapshot.selected_layer;
+        self.selection = snapshot.selection.clone();
+        self.set_object_position(MAP_OBJECT_LAYER, self.player_id, snapshot.player_position);
+
+        Ok(())
+    }
 
-            let creature = self.creature_factory.create(id);
-            let mut tries = 0;
-            
-            // don't place mobs in the same spot if possible
-            // 10 tries will be made to find a clear spot
-            loop {
-                let x = center[0] + spacing * (rng.gen::<f64>() * 10.0 - 5.0);
-                let y = center[1] + spacing * (rng.gen::<f64>() * 10.0 - 5.0);
     
-                let mut ok = true;
-                for mob in &list {
+    pub fn has_selection(&self) -> bool {
+        !self.selection.is_empty()
+    }
 
-                    let dx = mob.position[0] - x;
-                    let dy = mob.position[1] - y;
-                    let d = dx * dx + dy * dy;
-                    
-                    // must be at least 20 units from each other
-                    ok = d > 20.0 * 20.0;
-                }
 
-                tries += 1;
+    pub fn is_selected(&self, layer: usize, uid: u64) -> bool {
+        layer == self.selected_layer && self.selection.contains(&uid)
+    }
 
-                if ok {
-                    let mut mob = self.factory.create_mob(creature.base_tile_id, CREATURE_TILESET, [x, y], 32.0, scale);
-                    mob.mob_type = MobType::Creature;
+
+    /**
+     * Adds the object to the selection, or removes it if it was selected already
+     */
+    pub fn toggle_selection(&mut self, uid: u64) {
+        if let Some(index) = self.selection.iter().position(|selected| *selected == uid) {
+            self.selection.remove(index);
+        }
+        else {
+            self.selection.push(uid);
+        }
+    }
+
+
+    /**
+     * Selects the objects of the selected layer inside the rectangle. If add is true,
+     * they are added to the selection. Objects of locked layers can't be selected.
+     */
+    pub fn select_in_rect(&mut self, corner_1: &Vector2<f64>, corner_2: &Vector2<f64>, add: bool) {
+        if self.layer_flags[self.selected_layer].locked {
+            return;
+        }
+
+        let found = self.find_objects_in_rect(self.selected_layer, corner_1, corner_2);
+
+        if !add {
+            self.selection.clear();
+        }
+
+        for uid in found {
+            if uid != self.player_id && !self.selection.contains(&uid) {
+                self.selection.push(uid);
+            }
+        }
+    }
+
+
+    /**
+     * Drops objects from the selection which are no longer on the map,
+     * e.g. after an undo
+     */
+    pub fn prune_selection(&mut self) {
+        let layer = &self.layers[self.selected_layer];
+        self.selection.retain(|uid| layer.contains_key(uid));
+    }
+
+
+    pub fn move_selected_objects(&mut self, dx: f64, dy: f64) {
+        for uid in self.selection.clone() {
+            if let Some(object) = self.layers[self.selected_layer].get(&uid) {
+                let position = [object.position[0] + dx, object.position[1] + dy];
+                self.set_object_position(self.selected_layer, uid, position);
+            }
+        }
+    }
+
+
+    pub fn apply_to_selected_mobs<F>(&mut self, mut func: F)
+        where F: FnMut(&mut MapObject) {
+        for uid in &self.selection {
+            if let Some(mob) = self.layers[self.selected_layer].get_mut(uid) {
+                func(mob);
+            }
+        }
+    }
+
+
+    pub fn apply_to_mob<F>(&mut self, layer: usize, uid: u64, func: F)
+        where F: FnOnce(&mut MapObject) {
+        let mob = self.layers[layer].get_mut(&uid);
+
+        match mob {
+            None => {}
+            Some(mob) => { func(mob); }
+        }
+    }
+
+
+    pub fn make_creatures(&mut self, id: &str, min_count: i32, max_count: i32, center: Vector2<f64>, spacing: f64, scale: f64, rng: &mut StdRng) -> Vec<MapObject> {
+
+        let count = rng.gen_range(min_count ..= max_count) as usize;
+
+        let mut list: Vec<MapObject> = Vec::with_capacity(count);
+    
+        for _i in 0 .. count {
+
+            let creature = self.creature_factory.create(id);
+            let mut tries = 0;
+            
+            // don't place mobs in the same spot if possible
+            // 10 tries will be made to find a clear spot
+            loop {
+                let x = center[0] + spacing * (rng.gen::<f64>() * 10.0 - 5.0);
+                let y = center[1] + spacing * (rng.gen::<f64>() * 10.0 - 5.0);
+    
+                let mut ok = true;
+                for mob in &list {
+
+                    let dx = mob.position[0] - x;
+                    let dy = mob.position[1] - y;
+                    let d = dx * dx + dy * dy;
+                    
+                    // must be at least 20 units from each other
+                    ok = d > 20.0 * 20.0;
+                }
+
+                tries += 1;
+
+                if ok {
+                    let mut mob = self.factory.create_mob(creature.base_tile_id, CREATURE_TILESET, [x, y], 32.0, scale);
+                    mob.mob_type = MobType::Creature;
                     mob.creature = Some(creature);
                     mob.animation_timer = rng.gen::<f64>(); // otherwise all start with the very same frame
                     list.push(mob);
@@ -603,22 +1259,24 @@ impl Map {
     }
 
     
-    pub fn make_creature_group(&mut self, id: &str, min_count: i32, max_count: i32, center: Vector2<f64>, spacing: f64, rng: &mut StdRng) -> MobGroup {
+    pub fn make_creature_group(&mut self, population: &Population, rng: &mut StdRng) -> MobGroup {
         
-        let mut mobs = self.make_creatures(id, min_count, max_count, center, spacing, 0.5, rng);
+        let center = population.center;
+        let mut mobs = self.make_creatures(&population.creature_id, population.min_count, population.max_count, 
+                                           center, population.spacing, 0.5, rng);
         let mut list = Vec::new();
 
         for i in (0..mobs.len()).rev() {
             let mob = mobs.remove(i);
             let id = mob.uid;
 
-            self.layers[MAP_OBJECT_LAYER].insert(id, mob);
+            self.add_object(MAP_OBJECT_LAYER, mob);
             list.push(id);
         
             self.animations.insert(id, Box::new(SpinAnimation::new(12.0)));
         }
 
-        MobGroup::new(list, center, true, rng)
+        MobGroup::new(list, center, population.mobile, rng)
     }
 }
 
@@ -647,13 +1305,59 @@ fn emit_drive_particles(mob: &mut MapObject, dt: f64, rng: &mut StdRng) {
 }
 
 
-pub fn move_mob(mob: &mut MapObject, destination: Vector2<f64>, base_speed: f64) {
+/**
+ * Starts moving the mob towards the destination. The move ends in front
+ * of the first of the given obstacles which is in the way.
+ */
+pub fn move_mob(mob: &mut MapObject, destination: Vector2<f64>, base_speed: f64, blockers: &[Blocker]) {
+
+    let destination = clip_path(mob.position, destination, MOVER_RADIUS, blockers);
+
+    mob.path.clear();
+    head_to(mob, destination, base_speed);
+}
+
+
+/**
+ * Starts moving the mob along the waypoints, one segment after the other.
+ */
+pub fn move_mob_along_path(mob: &mut MapObject, path: Vec<Vector2<f64>>, base_speed: f64) {
+    mob.path = VecDeque::from(path);
+    mob.path_speed = base_speed;
+    mob.move_time_left = 0.0;
+
+    follow_path(mob);
+}
+
 
+/**
+ * Heads for the next waypoint of the mob's path, if there is one left.
+ */
+fn follow_path(mob: &mut MapObject) {
+    while let Some(waypoint) = mob.path.pop_front() {
+        if head_to(mob, waypoint, mob.path_speed) {
+            break;
+        }
+    }
+}
+
+
+/**
+ * @return true if the mob is moving now, false if it already is at the destination
+ */
+fn head_to(mob: &mut MapObject, destination: Vector2<f64>, base_speed: f64) -> bool {
     let direction = vec2_sub(destination, mob.position);
 
     println!("creature {} moves in direction {:?}", mob.uid, direction);
 
     let distance = vec2_len(direction);
+
+    if distance < 1.0 {
+        // blocked right away, or nowhere to go
+        mob.move_time_left = 0.0;
+        return false;
+    }
+
     let time = distance / base_speed; // pixel per second
 
     mob.move_time_left = time;
@@ -661,18 +1365,24 @@ pub fn move_mob(mob: &mut MapObject, destination: Vector2<f64>, base_speed: f64)
 
     let d = mob.visual.orient(direction);
     mob.visual.current_image_id = mob.visual.base_image_id + d;
+
+    true
 }
 
 
-fn blend_to_key(blend: &BlendMode) -> String {
-    let key =
-        match blend {
-            BlendMode::Blend => {"n"}, 
-            BlendMode::Add => {"a"},
-            _ => {panic!("Unsupported blend mode")},
-        };
+fn blend_to_key(blend: &BlendMode) -> &'static str {
+    match blend {
+        BlendMode::Blend => "n",
+        BlendMode::Add => "a",
+    }
+}
 
-    key.to_string()
+
+fn footprint_to_key(footprint: &Footprint) -> String {
+    match footprint {
+        Footprint::FromTile => "auto".to_string(),
+        Footprint::Radius(radius) => format_number(*radius),
+    }
 }
 
 
@@ -690,32 +1400,71 @@ fn key_to_blend(key: &str) -> BlendMode {
 }
 
 
+// only the map decorations are saved, the runtime state is skipped
+#[derive(Serialize, Deserialize)]
 pub struct MapObject {
 
+    #[serde(skip)]
     pub mob_type: MobType,
     pub uid: u64,
     pub visual: Visual,
+    #[serde(skip)]
     pub creature: Option<Creature>,
+    #[serde(skip)]
     pub item: Option<Item>,
 
     // world coordinates of this object. Note that screen coordinates are different
+    #[serde(serialize_with = "serialize_rounded_vec2")]
     pub position: Vector2<f64>,
+    #[serde(skip)]
     pub velocity: Vector2<f64>,
+    #[serde(skip)]
     pub move_time_left: f64,
 
+    #[serde(skip)]
     pub move_end_action: MoveEndAction,
+
+    // waypoints which are yet to visit after the current move
+    #[serde(skip)]
+    pub path: VecDeque<Vector2<f64>>,
+    #[serde(skip)]
+    pub path_speed: f64,
+
+    #[serde(skip)]
     pub update_action: UpdateAction,
+    #[serde(skip)]
     pub animation_timer: f64,
+
+    pub footprint: Footprint,
 }
 
 
 impl MapObject {
     
-    pub fn move_dt(&mut self, dt: f64) {
+    /**
+     * Moves this object along its current velocity. Blockers make it slide 
+     * along their rim, or stop if there is no way around them.
+     */
+    pub fn move_dt(&mut self, dt: f64, blockers: &[Blocker]) {
         if self.move_time_left > 0.0 {
             let distance = vec2_scale(self.velocity, dt);
-            self.position = vec2_add(self.position, distance);
-            self.move_time_left -= dt;
+
+            if blockers.is_empty() {
+                self.position = vec2_add(self.position, distance);
+                self.move_time_left -= dt;
+            }
+            else {
+                let position = slide_move(self.position, distance, MOVER_RADIUS, blockers);
+
+                if position == self.position {
+                    // stuck
+                    self.move_time_left = 0.0;
+                }
+                else {
+                    self.position = position;
+                    self.move_time_left -= dt;
+                }
+            }
         }
     }
 }
@@ -728,6 +1477,26 @@ pub struct MapObjectFactory {
 
 impl MapObjectFactory {
 
+    pub fn next_uid(&mut self) -> u64 {
+        let uid = self.next_id;
+        self.next_id += 1;
+
+        println!("MapObjectFactory: next id will be {}", self.next_id);
+
+        uid
+    }
+
+
+    /**
+     * Makes sure that this id won't be handed out to new objects
+     */
+    pub fn reserve_id(&mut self, uid: u64) {
+        if uid >= self.next_id {
+            self.next_id = uid + 1;
+        }
+    }
+
+
     pub fn create_mob(&mut self, tile_id: usize, tileset_id: usize, position: Vector2<f64>, height: f64, scale: f64) -> MapObject {
 
         let visual = Visual {
@@ -744,10 +1513,7 @@ impl MapObjectFactory {
             particles: ParticleDriver::new(),
         };
 
-        let uid = self.next_id;
-        self.next_id += 1;
-
-        println!("MapObjectFactory: next id will be {}", self.next_id);
+        let uid = self.next_uid();
 
         MapObject {
             mob_type: MobType::MapObject,
@@ -761,44 +1527,79 @@ impl MapObjectFactory {
             move_time_left: 0.0,
 
             move_end_action: MoveEndAction::None,
+            path: VecDeque::new(),
+            path_speed: 0.0,
             update_action: UpdateAction::None,
             animation_timer: 0.0,
+
+            footprint: Footprint::FromTile,
         }
     }
 }
 
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Default)]
 pub enum MoveEndAction {
+    #[default]
     None,
     RemoveFromMap,
 }
 
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Default)]
 pub enum UpdateAction {
+    #[default]
     None,
     RemoveFromMap,
     EmitDriveParticles,
 }
 
 
+#[derive(Serialize, Deserialize)]
 pub struct Visual {
     pub base_image_id: usize,
     pub current_image_id: usize,
     pub directions: usize,
     pub phases: usize, // animation phases per direction 
     pub tileset_id: usize,
+    #[serde(serialize_with = "serialize_rounded")]
     pub height: f64,
+    #[serde(serialize_with = "serialize_rounded")]
     pub scale: f64,
+    #[serde(serialize_with = "serialize_rounded_color")]
     pub color: [f32; 4],
+    #[serde(serialize_with = "serialize_rounded_color")]
     pub glow: [f32; 4], // ground illumination color
     pub blend: BlendMode,
+    #[serde(rename = "emitter", with = "crate::particle_driver::emitter_serde",
+            default = "ParticleDriver::new", skip_serializing_if = "ParticleDriver::has_no_emitter")]
     pub particles: ParticleDriver,
 }
 
 
 impl Visual {
+
+    /**
+     * Copies the look of this visual. Particles which are currently
+     * alive are not copied, only the settings to spawn them.
+     */
+    pub fn duplicate(&self) -> Visual {
+        Visual {
+            base_image_id: self.base_image_id,
+            current_image_id: self.current_image_id,
+            directions: self.directions,
+            phases: self.phases,
+            tileset_id: self.tileset_id,
+            height: self.height,
+            scale: self.scale,
+            color: self.color,
+            glow: self.glow,
+            blend: self.blend,
+            particles: self.particles.copy_settings(),
+        }
+    }
+
+
     pub fn orient(&self, direction: Vector2<f64>) -> usize {
         let directions = self.directions;
         let mut result = 0;
@@ -840,8 +1641,9 @@ impl Visual {
 }
 
 
-#[derive(PartialEq, Clone, Copy)]
+#[derive(PartialEq, Clone, Copy, Default)]
 pub enum MobType {
+    #[default]
     MapObject,
     Player,
     Creature,
@@ -850,12 +1652,621 @@ pub enum MobType {
 }
 
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LayerInfo {
+    pub name: String,
+    pub tileset_id: usize,
+    pub visible: bool,
+}
+
+
+impl LayerInfo {
+
+    pub fn default_for(layer: usize) -> LayerInfo {
+        let name = 
+            match layer {
+                MAP_GROUND_LAYER => "Ground".to_string(),
+                MAP_OBJECT_LAYER => "Objects".to_string(),
+                MAP_CLOUD_LAYER => "Clouds".to_string(),
+                _ => "Layer ".to_string() + &(layer + 1).to_string(),
+            };
+
+        LayerInfo {
+            name,
+            tileset_id: layer,
+            visible: true,
+        }
+    }
+}
+
+
+/**
+ * Editor settings of a layer
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LayerFlags {
+    // not drawn in the editor
+    pub hidden: bool,
+
+    // objects on this layer can't be selected or changed
+    pub locked: bool,
+}
+
+
+fn load_layer_info(reader: &MapReader, line: &str) -> Result<LayerInfo, MapLoadError> {
+    let parts: Vec<&str> = line.split(",").collect();
+
+    let tileset_id = reader.parse_field::<usize>(&parts, 1, "tileset")?;
+    if tileset_id >= TILESET_COUNT {
+        return Err(reader.invalid_field("tileset", parts[1]));
+    }
+
+    Ok(LayerInfo {
+        name: reader.field(&parts, 0, "layer name")?.to_string(),
+        tileset_id,
+        visible: reader.parse_field::<bool>(&parts, 2, "visible")?,
+    })
+}
+
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct MapTransition {
 
     // entrance location
-    from: Vector2<f64>,
+    #[serde(serialize_with = "serialize_rounded_vec2")]
+    pub from: Vector2<f64>,
     // catchment area
-    rad: f64,
-    // destination map
-    to: i32,
-}
\ No newline at end of file
+    #[serde(serialize_with = "serialize_rounded")]
+    pub rad: f64,
+    // destination map file name
+    pub to_map: String,
+    // arrival location on the destination map
+    #[serde(serialize_with = "serialize_rounded_vec2")]
+    pub to_position: Vector2<f64>,
+    // direction the player faces after arrival
+    pub facing: usize,
+}
+
+
+/**
+ * A copy of the map state, see Map::snapshot()
+ */
+pub struct MapSnapshot {
+    content: String,
+    filename: String,
+    layer_flags: [LayerFlags; MAP_LAYER_COUNT],
+    selected_layer: usize,
+    selection: Vec<u64>,
+    player_position: Vector2<f64>,
+}
+
+
+/**
+ * The map data as it was read from a file, before it is put into the map
+ */
+pub(crate) struct MapData {
+    pub name: String,
+    pub map_image_name: String,
+    pub backdrop_image_name: String,
+    pub layer_info: [LayerInfo; MAP_LAYER_COUNT],
+    pub objects: Vec<(usize, MapObject)>,
+    pub transitions: Vec<MapTransition>,
+    pub populations: Vec<Population>,
+}
+
+
+#[derive(Debug)]
+pub enum MapLoadError {
+    Io { file: String, error: std::io::Error },
+    UnexpectedEnd { file: String, line: usize, expected: String },
+    UnexpectedLine { file: String, line: usize, expected: String, found: String },
+    MissingField { file: String, line: usize, field: String },
+    InvalidField { file: String, line: usize, field: String, value: String },
+    Format { file: String, line: usize, message: String },
+}
+
+
+impl std::fmt::Display for MapLoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            MapLoadError::Io { file, error } => 
+                write!(f, "{}: cannot read map file: {}", file, error),
+            MapLoadError::UnexpectedEnd { file, line, expected } => 
+                write!(f, "{}:{}: unexpected end of file, expected {}", file, line, expected),
+            MapLoadError::UnexpectedLine { file, line, expected, found } => 
+                write!(f, "{}:{}: expected '{}' but found '{}'", file, line, expected, found),
+            MapLoadError::MissingField { file, line, field } => 
+                write!(f, "{}:{}: missing field '{}'", file, line, field),
+            MapLoadError::InvalidField { file, line, field, value } => 
+                write!(f, "{}:{}: invalid value '{}' for field '{}'", file, line, value, field),
+            MapLoadError::Format { file, line, message } => 
+                write!(f, "{}:{}: {}", file, line, message),
+        }
+    }
+}
+
+
+impl std::error::Error for MapLoadError {
+}
+
+
+/**
+ * Walks through the lines of a map file and keeps track of the
+ * line number, so that errors can tell where things went wrong.
+ */
+struct MapReader<'a> {
+    file: &'a str,
+    lines: Lines<'a>,
+    line_no: usize,
+}
+
+
+impl <'a> MapReader<'a> {
+
+    fn new(file: &'a str, content: &'a str) -> MapReader<'a> {
+        MapReader {
+            file,
+            lines: content.lines(),
+            line_no: 0,
+        }
+    }
+
+
+    fn next_line(&mut self, expected: &str) -> Result<&'a str, MapLoadError> {
+        match self.lines.next() {
+            None => {
+                Err(MapLoadError::UnexpectedEnd { 
+                    file: self.file.to_string(), 
+                    line: self.line_no, 
+                    expected: expected.to_string() 
+                })
+            },
+            Some(line) => {
+                self.line_no += 1;
+                Ok(line)
+            }
+        }
+    }
+
+
+    fn next_optional_line(&mut self) -> Option<&'a str> {
+        let line = self.lines.next();
+
+        if line.is_some() {
+            self.line_no += 1;
+        }
+
+        line
+    }
+
+
+    fn expect_marker(&mut self, marker: &str) -> Result<(), MapLoadError> {
+        let line = self.next_line(marker)?;
+
+        if line == marker {
+            Ok(())
+        }
+        else {
+            Err(self.unexpected_line(marker, line))
+        }
+    }
+
+
+    /**
+     * Hands each line up to the end marker to the given function
+     */
+    fn read_section<F>(&mut self, end_marker: &str, mut func: F) -> Result<(), MapLoadError>
+        where F: FnMut(&MapReader, &str) -> Result<(), MapLoadError> {
+
+        let mut line = self.next_line(end_marker)?;
+
+        while line != end_marker {
+            println!("line='{}'", line);
+            func(self, line)?;
+            line = self.next_line(end_marker)?;
+        }
+
+        Ok(())
+    }
+
+
+    fn unexpected_line(&self, expected: &str, found: &str) -> MapLoadError {
+        MapLoadError::UnexpectedLine { 
+            file: self.file.to_string(), 
+            line: self.line_no, 
+            expected: expected.to_string(), 
+            found: found.to_string() 
+        }
+    }
+
+
+    fn field<'b>(&self, parts: &[&'b str], index: usize, field: &str) -> Result<&'b str, MapLoadError> {
+        match parts.get(index) {
+            None => {
+                Err(MapLoadError::MissingField { 
+                    file: self.file.to_string(), 
+                    line: self.line_no, 
+                    field: field.to_string() 
+                })
+            },
+            Some(value) => Ok(value)
+        }
+    }
+
+
+    fn parse_field<T: FromStr>(&self, parts: &[&str], index: usize, field: &str) -> Result<T, MapLoadError> {
+        let value = self.field(parts, index, field)?;
+        value.trim().parse::<T>().map_err(|_| self.invalid_field(field, value))
+    }
+
+
+    fn invalid_field(&self, field: &str, value: &str) -> MapLoadError {
+        MapLoadError::InvalidField {
+            file: self.file.to_string(),
+            line: self.line_no,
+            field: field.to_string(),
+            value: value.to_string(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+
+    fn describe_objects(map: &Map, layer: usize) -> Vec<String> {
+        let mut result: Vec<String> = map.layers[layer].values()
+            .filter(|mob| mob.uid != map.player_id && mob.mob_type == MobType::MapObject)
+            .map(|mob| format!("{} {} {} {} {:?} {} {} {:?} {:?} {:?} {:?}",
+                mob.uid, mob.visual.tileset_id, mob.visual.base_image_id, mob.visual.directions,
+                mob.position, mob.visual.height, mob.visual.scale, mob.visual.color,
+                mob.visual.blend, mob.footprint, mob.visual.particles.emitter()))
+            .collect();
+
+        result.sort();
+        result
+    }
+
+
+    #[test]
+    fn test_move_selection() {
+        let mut map = Map::new("", "", "");
+        let layer = MAP_GROUND_LAYER;
+        let mut uids = Vec::new();
+
+        for i in 0..3 {
+            let mob = map.factory.create_mob(5, layer, [i as f64 * 100.0, 0.0], 0.0, 1.0);
+            uids.push(mob.uid);
+            map.add_object(layer, mob);
+        }
+
+        map.selected_layer = layer;
+        map.toggle_selection(uids[0]);
+        map.toggle_selection(uids[2]);
+        map.move_selected_objects(10.0, 20.0);
+
+        assert_eq!(map.layers[layer][&uids[0]].position, [10.0, 20.0]);
+        assert_eq!(map.layers[layer][&uids[1]].position, [100.0, 0.0]);
+        assert_eq!(map.layers[layer][&uids[2]].position, [210.0, 20.0]);
+        assert_eq!(map.find_objects_in_rect(layer, &[0.0, 10.0], &[300.0, 30.0]).len(), 2);
+
+        map.remove_object(layer, uids[2]);
+        map.prune_selection();
+        assert_eq!(map.selection, vec![uids[0]]);
+    }
+
+
+    #[test]
+    fn test_find_transition() {
+        let mut map = Map::new("", "", "");
+
+        for (x, rad) in [(0.0, 100.0), (150.0, 100.0), (1000.0, 50.0)] {
+            map.transitions.push(MapTransition {
+                from: [x, 0.0],
+                rad,
+                to_map: "start.map".to_string(),
+                to_position: [0.0, 0.0],
+                facing: 0,
+            });
+        }
+
+        assert_eq!(map.find_transition(&[10.0, 0.0]), Some(0));
+        assert_eq!(map.find_transition(&[90.0, 0.0]), Some(1));
+        assert_eq!(map.find_transition(&[1000.0, 60.0]), None);
+    }
+
+
+    #[test]
+    fn test_failed_transition_waits_for_leaving() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut map = Map::new("", "", "");
+
+        map.transitions.push(MapTransition {
+            from: map.player_position(),
+            rad: 50.0,
+            to_map: "no_such_map.map".to_string(),
+            to_position: [0.0, 0.0],
+            facing: 0,
+        });
+
+        assert!(!map.check_player_transition(&mut rng));
+        assert_eq!(map.failed_transition, Some(0));
+
+        map.set_object_position(MAP_OBJECT_LAYER, map.player_id, [0.0, 0.0]);
+        assert!(!map.check_player_transition(&mut rng));
+        assert_eq!(map.failed_transition, None);
+    }
+
+
+    #[test]
+    fn test_layer_flags_per_map() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut map = Map::new("Flags", "", "");
+        let content = map.to_file_content().unwrap();
+
+        map.filename = "flags_test_1.map".to_string();
+        map.layer_flags[MAP_CLOUD_LAYER].hidden = true;
+        map.layer_flags[MAP_GROUND_LAYER].locked = true;
+
+        map.load_content("flags_test_2.map", &content, &mut rng).unwrap();
+        assert_eq!(map.layer_flags, [LayerFlags::default(); MAP_LAYER_COUNT]);
+
+        map.load_content("flags_test_1.map", &content, &mut rng).unwrap();
+        assert!(map.layer_flags[MAP_CLOUD_LAYER].hidden);
+        assert!(map.layer_flags[MAP_GROUND_LAYER].locked);
+        assert!(!map.layer_flags[MAP_OBJECT_LAYER].locked);
+    }
+
+
+    #[test]
+    fn test_drop_invalid_emitters() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut map = Map::new("Emitters", "", "");
+
+        for tileset_id in [MAP_CLOUD_LAYER, 99] {
+            let mut mob = map.factory.create_mob(212, MAP_OBJECT_LAYER, [100.0, 100.0], 0.0, 1.0);
+            mob.visual.particles = ParticleDriver::with_emitter(EmitterSettings { tileset_id, ..EmitterSettings::default() });
+            map.add_object(MAP_OBJECT_LAYER, mob);
+        }
+
+        let content = map.to_file_content().unwrap();
+        let data = map.parse_structured("emitter_test.map", &content).unwrap();
+        map.apply_map_data(data, &mut rng);
+
+        let tilesets: Vec<usize> = map.layers[MAP_OBJECT_LAYER].values()
+            .filter_map(|mob| mob.visual.particles.emitter())
+            .map(|emitter| emitter.tileset_id)
+            .collect();
+        assert_eq!(tilesets, [MAP_CLOUD_LAYER]);
+    }
+
+
+    #[test]
+    fn test_locked_layer_selection() {
+        let mut map = Map::new("", "", "");
+        let layer = MAP_GROUND_LAYER;
+        map.selected_layer = layer;
+
+        let mob = map.factory.create_mob(5, layer, [100.0, 100.0], 0.0, 1.0);
+        let uid = mob.uid;
+        map.add_object(layer, mob);
+
+        map.layer_flags[layer].locked = true;
+        map.select_in_rect(&[0.0, 0.0], &[200.0, 200.0], false);
+        assert!(map.selection.is_empty());
+
+        map.layer_flags[layer].locked = false;
+        map.select_in_rect(&[0.0, 0.0], &[200.0, 200.0], false);
+        assert_eq!(map.selection, [uid]);
+    }
+
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut map = Map::new("Snapshot", "map_image.png", "backdrop.png");
+        let layer = MAP_GROUND_LAYER;
+
+        for i in 0..3 {
+            let mob = map.factory.create_mob(10, layer, [100.0 * i as f64, 50.0], 0.0, 1.0);
+            map.add_object(layer, mob);
+        }
+
+        map.selected_layer = layer;
+        map.selection = map.layers[layer].keys().take(1).copied().collect();
+        map.layer_flags[MAP_CLOUD_LAYER].hidden = true;
+        let before = describe_objects(&map, layer);
+        let player_position = map.player_position();
+        let snapshot = map.snapshot().unwrap();
+
+        // playing changes the map
+        let uid = map.selection[0];
+        map.remove_object(layer, uid);
+        let mob = map.factory.create_mob(11, layer, [500.0, 500.0], 0.0, 1.0);
+        map.add_object(layer, mob);
+        map.set_object_position(MAP_OBJECT_LAYER, map.player_id, [900.0, 900.0]);
+        map.layer_flags = [LayerFlags::default(); MAP_LAYER_COUNT];
+        map.selection.clear();
+
+        map.restore(&snapshot, &mut rng).unwrap();
+
+        assert_eq!(describe_objects(&map, layer), before);
+        assert_eq!(map.selection, vec![uid]);
+        assert_eq!(map.player_position(), player_position);
+        assert!(map.layer_flags[MAP_CLOUD_LAYER].hidden);
+        assert_eq!(map.find_nearest_object(layer, &[500.0, 500.0], 10.0, 0), None);
+    }
+
+
+    #[test]
+    fn test_save_load_round_trip() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut map = Map::new("Round Trip", "map_image.png", "backdrop.png");
+
+        map.layer_info[5].name = "Overlays".to_string();
+        map.layer_info[5].tileset_id = MAP_CLOUD_LAYER;
+        map.layer_info[6].visible = false;
+
+        for layer in 0..MAP_LAYER_COUNT {
+            let tileset_id = map.layer_info[layer].tileset_id;
+
+            for i in 0..3 {
+                let position = [100.0 + layer as f64 * 10.5, 200.25 * i as f64];
+                let mut mob = map.factory.create_mob(10 + i, tileset_id, position, 12.5, 0.75);
+                mob.visual.color = [0.1, 0.2, 0.3 * i as f32, 1.0];
+                mob.visual.blend = if i == 1 { BlendMode::Add } else { BlendMode::Blend };
+                mob.visual.directions = 1 + i;
+                mob.footprint = if i == 2 { Footprint::Radius(33.5) } else { Footprint::FromTile };
+
+                if i == 1 {
+                    let emitter = EmitterSettings { spawn_ids: vec![3, 4], rate: 12.5, lifetime: [0.5, 1.5], ..EmitterSettings::default() };
+                    mob.visual.particles = ParticleDriver::with_emitter(emitter);
+                }
+
+                map.add_object(layer, mob);
+            }
+        }
+
+        map.transitions.push(MapTransition {
+            from: [10.0, 20.0],
+            rad: 50.0,
+            to_map: "other.map".to_string(),
+            to_position: [300.0, 400.0],
+            facing: 3,
+        });
+
+        let content = map.to_file_content().unwrap();
+
+        let mut loaded = Map::new("Empty", "", "");
+        loaded.load_content("round_trip_test.map", &content, &mut rng).unwrap();
+
+        assert_eq!(loaded.name, map.name);
+        assert_eq!(loaded.map_image_name, map.map_image_name);
+        assert_eq!(loaded.backdrop_image_name, map.backdrop_image_name);
+        assert_eq!(loaded.layer_info, map.layer_info);
+
+        for layer in 0..MAP_LAYER_COUNT {
+            assert_eq!(describe_objects(&loaded, layer).len(), 3);
+            assert_eq!(describe_objects(&loaded, layer), describe_objects(&map, layer));
+        }
+
+        assert_eq!(loaded.transitions.len(), 1);
+        assert_eq!(loaded.transitions[0].to_map, "other.map");
+        assert_eq!(loaded.transitions[0].to_position, [300.0, 400.0]);
+        assert_eq!(loaded.transitions[0].facing, 3);
+    }
+
+
+    #[test]
+    fn test_saving_is_stable() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut map = Map::new("Stable", "map_image.png", "backdrop.png");
+
+        for i in 0..20 {
+            let position = [0.1 * 3.0 * i as f64, 1000.0 / 3.0];
+            let mob = map.factory.create_mob(10, MAP_OBJECT_LAYER, position, 0.0, 1.0);
+            map.add_object(MAP_OBJECT_LAYER, mob);
+        }
+
+        let first = map.to_file_content().unwrap();
+        map.load_content("stable_test.map", &first, &mut rng).unwrap();
+        let second = map.to_file_content().unwrap();
+
+        assert_eq!(first, second);
+        assert!(first.contains("333.333"));
+        assert!(!first.contains("333.3333"));
+    }
+
+
+    #[test]
+    fn test_reject_zero_directions() {
+        let mut map = Map::new("", "", "");
+        let header = "v10\nbegin map header\nTest\nmap.png\nbackdrop.png\nend map header\nbegin map objects\n";
+
+        let content = header.to_string() + "0,31,8,100,200,66,1,1 1 1 1,n\nend map objects\n";
+        assert_eq!(map.parse_v10("test.map", &content).unwrap().objects.len(), 1);
+
+        let content = header.to_string() + "0,31,0,100,200,66,1,1 1 1 1,n\nend map objects\n";
+        assert!(matches!(map.parse_v10("test.map", &content),
+                         Err(MapLoadError::InvalidField { line: 8, .. })));
+    }
+
+
+    #[test]
+    fn test_reject_unknown_tileset() {
+        let mut map = Map::new("", "", "");
+        let header = "v10\nbegin map header\nTest\nmap.png\nbackdrop.png\nlayers,1\n";
+
+        let content = header.to_string() + "Ground,7,true\nend map header\n";
+        assert_eq!(map.parse_v10("test.map", &content).unwrap().layer_info[0].tileset_id, 7);
+
+        let content = header.to_string() + "Ground,8,true\nend map header\n";
+        assert!(matches!(map.parse_v10("test.map", &content),
+                         Err(MapLoadError::InvalidField { line: 7, .. })));
+    }
+
+
+    #[test]
+    fn test_save_v10_round_trip() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut map = Map::new("Old", "map_image.png", "backdrop.png");
+
+        for i in 0..20 {
+            let position = [0.1 * 3.0 * i as f64, 1000.0 / 3.0];
+            let mob = map.factory.create_mob(10, MAP_OBJECT_LAYER, position, 0.0, 1.0);
+            map.add_object(MAP_OBJECT_LAYER, mob);
+        }
+
+        let mut first = Vec::new();
+        map.write_v10(&mut first).unwrap();
+        let first = String::from_utf8(first).unwrap();
+        map.load_content("v10_test.map", &first, &mut rng).unwrap();
+
+        let mut second = Vec::new();
+        map.write_v10(&mut second).unwrap();
+        let second = String::from_utf8(second).unwrap();
+
+        assert!(first.starts_with("v10\n"));
+        assert_eq!(first, second);
+        assert!(first.contains(",333.333,"));
+        assert_eq!(describe_objects(&map, MAP_OBJECT_LAYER).len(), 20);
+    }
+
+
+    #[test]
+    fn test_format_number() {
+        assert_eq!(format_number(2.3999999999999995), "2.4");
+        assert_eq!(format_number(100.0), "100");
+        assert_eq!(format_number(-0.0001), "0");
+        assert_eq!(format_number(-12.125), "-12.125");
+        assert_eq!(format_number(0.5), "0.5");
+    }
+
+
+    #[test]
+    fn test_convert_v10_map() {
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let mut old = Map::new("", "", "");
+        old.load("warmup.map", &mut rng).unwrap();
+
+        // this is what convert_map() writes
+        let content = old.to_file_content().unwrap();
+
+        let mut converted = Map::new("", "", "");
+        converted.load_content("convert_test.map", &content, &mut rng).unwrap();
+
+        assert!(!content.starts_with("v10"));
+        assert_eq!(converted.name, old.name);
+        assert_eq!(converted.layer_info, old.layer_info);
+        assert_eq!(converted.transitions.len(), old.transitions.len());
+        assert_eq!(converted.populations.len(), old.populations.len());
+
+        for layer in 0..MAP_LAYER_COUNT {
+            assert_eq!(describe_objects(&converted, layer).len(), describe_objects(&old, layer).len());
+        }
+    }
+}
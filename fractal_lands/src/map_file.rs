@@ -0,0 +1,167 @@
+use serde::{Serialize, Deserialize, Serializer};
+use ron::ser::PrettyConfig;
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use vecmath::Vector2;
+
+use crate::map::{Map, MapObject, MapTransition, LayerInfo};
+use crate::mob_group::Population;
+
+
+/**
+ * Version of the structured map format. Files without a version line
+ * of the older, line oriented format are expected to be in this format.
+ */
+pub const MAP_FORMAT_VERSION: u32 = 12;
+
+
+/**
+ * Map files are written in the structured format, the older v10 format
+ * can still be chosen when converting maps.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MapFormat {
+    V10,
+    Structured,
+}
+
+
+/**
+ * The structured map file, as it is read from disk.
+ */
+#[derive(Deserialize)]
+pub struct MapFile {
+    pub version: u32,
+    pub name: String,
+    pub map_image_name: String,
+    pub backdrop_image_name: String,
+    pub layers: Vec<MapFileLayer>,
+    pub transitions: Vec<MapTransition>,
+
+    // mob groups are stored as the populations they are spawned from
+    pub populations: Vec<Population>,
+}
+
+
+#[derive(Deserialize)]
+pub struct MapFileLayer {
+    pub info: LayerInfo,
+    pub objects: Vec<MapObject>,
+}
+
+
+/**
+ * The structured map file, as it is written to disk. It borrows
+ * everything from the map, so nothing needs to be copied.
+ */
+#[derive(Serialize)]
+pub struct MapFileRef<'a> {
+    pub version: u32,
+    pub name: &'a str,
+    pub map_image_name: &'a str,
+    pub backdrop_image_name: &'a str,
+    pub layers: Vec<MapFileLayerRef<'a>>,
+    pub transitions: &'a [MapTransition],
+    pub populations: &'a [Population],
+}
+
+
+#[derive(Serialize)]
+pub struct MapFileLayerRef<'a> {
+    pub info: &'a LayerInfo,
+    pub objects: Vec<&'a MapObject>,
+}
+
+
+pub fn to_ron(map_file: &MapFileRef) -> Result<String, ron::Error> {
+    let config = PrettyConfig::new()
+        .depth_limit(4)
+        .indentor("  ".to_string());
+
+    ron::ser::to_string_pretty(map_file, config)
+}
+
+
+pub fn from_ron(content: &str) -> Result<MapFile, ron::error::SpannedError> {
+    ron::from_str(content)
+}
+
+
+/**
+ * Converts a map file of any format to the given format. Both files are
+ * in the maps directory.
+ */
+pub fn convert_map(source: &str, destination: &str, format: MapFormat) -> Result<(), Box<dyn std::error::Error>> {
+    let mut map = Map::new("", "", "");
+
+    // populations are only spawned, not saved, so the seed doesn't matter
+    let mut rng = StdRng::seed_from_u64(0);
+
+    map.load(source, &mut rng)?;
+
+    match format {
+        MapFormat::V10 => map.save_v10(destination)?,
+        MapFormat::Structured => map.save(destination)?,
+    }
+
+    Ok(())
+}
+
+
+/**
+ * @return the names of all map files in the maps directory, sorted alphabetically
+ */
+pub fn list_map_files() -> std::io::Result<Vec<String>> {
+    let mut files = Vec::new();
+
+    for entry in std::fs::read_dir("maps")? {
+        let path = entry?.path();
+
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "map") {
+            if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+                files.push(name.to_string());
+            }
+        }
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
+
+/**
+ * Rounds to three decimals, so that tiny rounding differences don't
+ * show up in the map files.
+ */
+pub fn round_number(value: f64) -> f64 {
+    let rounded = (value * 1000.0).round() / 1000.0;
+
+    // no negative zeros
+    if rounded == 0.0 { 0.0 } else { rounded }
+}
+
+
+/**
+ * Writes a number rounded like in the map files, without trailing zeros
+ */
+pub fn format_number(value: f64) -> String {
+    round_number(value).to_string()
+}
+
+
+pub fn serialize_rounded<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(round_number(*value))
+}
+
+
+pub fn serialize_rounded_vec2<S: Serializer>(value: &Vector2<f64>, serializer: S) -> Result<S::Ok, S::Error> {
+    [round_number(value[0]), round_number(value[1])].serialize(serializer)
+}
+
+
+pub fn serialize_rounded_color<S: Serializer>(value: &[f32; 4], serializer: S) -> Result<S::Ok, S::Error> {
+    let rounded = value.map(|c| round_number(c as f64) as f32);
+    rounded.serialize(serializer)
+}
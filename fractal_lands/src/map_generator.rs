@@ -0,0 +1,458 @@
+use rand::Rng;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use serde::Deserialize;
+use vecmath::{Vector2, vec2_sub, vec2_square_len};
+
+use crate::map::{Map, MapData, MapObject, MapTransition, LayerInfo};
+use crate::map::{MAP_GROUND_LAYER, MAP_OBJECT_LAYER, MAP_CLOUD_LAYER};
+use crate::mob_group::Population;
+use crate::gl_support::BlendMode;
+
+
+/**
+ * Describes what the map generator puts on a map. Missing entries in a
+ * config file are taken from the default config.
+ */
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct GeneratorConfig {
+    pub seed: u64,
+    pub name: String,
+    pub map_image_name: String,
+    pub backdrop_image_name: String,
+
+    // world coordinates
+    pub size: Vector2<f64>,
+
+    // the map is scanned in cells of this size, each cell gets at most one object per layer
+    pub cell_size: f64,
+
+    pub ground_tiles: Vec<usize>,
+    pub decoration_tiles: Vec<usize>,
+    pub cloud_tiles: Vec<usize>,
+
+    // chance to place an object in a cell, where the terrain allows it
+    pub ground_density: f64,
+    pub decoration_density: f64,
+    pub cloud_density: f64,
+
+    // 0.0 gives smooth terrain, 1.0 very rugged terrain
+    pub roughness: f64,
+
+    pub creatures: Vec<String>,
+    pub population_count: usize,
+
+    pub transitions: Vec<GeneratedTransition>,
+}
+
+
+#[derive(Deserialize, Clone)]
+pub struct GeneratedTransition {
+    pub to_map: String,
+    pub to_position: Vector2<f64>,
+    pub facing: usize,
+}
+
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        GeneratorConfig {
+            seed: 0,
+            name: "Generated Map".to_string(),
+            map_image_name: "map_puzzle_technoland.png".to_string(),
+            backdrop_image_name: "backdrop_red_blue.png".to_string(),
+            size: [2400.0, 3600.0],
+            cell_size: 96.0,
+            ground_tiles: vec![27, 28, 29, 30, 31, 32],
+            decoration_tiles: (16 ..= 72).collect(),
+            cloud_tiles: vec![1, 2],
+            ground_density: 0.3,
+            decoration_density: 0.5,
+            cloud_density: 0.15,
+            roughness: 0.55,
+            creatures: vec!["Targetting Drone".to_string()],
+            population_count: 2,
+            transitions: vec![
+                GeneratedTransition {
+                    to_map: "start.map".to_string(),
+                    to_position: [1000.0, 1000.0],
+                    facing: 0,
+                }
+            ],
+        }
+    }
+}
+
+
+impl GeneratorConfig {
+
+    /**
+     * Reads a generator config file from the maps directory
+     */
+    pub fn load(filename: &str) -> Result<GeneratorConfig, String> {
+        let path = std::path::Path::new("maps").join(filename);
+        let content = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+
+        ron::from_str(&content).map_err(|error| error.to_string())
+    }
+
+
+    /**
+     * Drops the tiles which are missing in the tileset of their layer.
+     *
+     * @param tile_exists Tells if a tileset (first parameter) has a tile (second parameter)
+     */
+    pub fn remove_unknown_tiles(&mut self, tile_exists: impl Fn(usize, usize) -> bool) {
+        let lists = [
+            (MAP_GROUND_LAYER, &mut self.ground_tiles),
+            (MAP_OBJECT_LAYER, &mut self.decoration_tiles),
+            (MAP_CLOUD_LAYER, &mut self.cloud_tiles),
+        ];
+
+        for (layer, tiles) in lists {
+            let tileset_id = LayerInfo::default_for(layer).tileset_id;
+
+            tiles.retain(|tile_id| {
+                let exists = tile_exists(tileset_id, *tile_id);
+                if !exists {
+                    println!("Warning: tileset {} has no tile {}, the generator will not use it", tileset_id, tile_id);
+                }
+                exists
+            });
+        }
+    }
+}
+
+
+// terrain below this level gets ground patches, above it decorations may grow
+const GROUND_LEVEL: f64 = 0.45;
+const DECORATION_LEVEL: f64 = 0.55;
+const CLOUD_LEVEL: f64 = 0.6;
+
+// decorations keep this distance from transitions and population centers
+const CLEARING_RADIUS: f64 = 200.0;
+
+
+/**
+ * A square field of values between 0 and 1, made by midpoint displacement.
+ */
+pub struct HeightField {
+    size: usize,
+    values: Vec<f64>,
+}
+
+
+impl HeightField {
+
+    /**
+     * Makes a field with 2^detail + 1 values per side.
+     */
+    pub fn new(detail: u32, roughness: f64, rng: &mut StdRng) -> HeightField {
+        let size = (1 << detail) + 1;
+        let mut field = HeightField {
+            size,
+            values: vec![0.0; size * size],
+        };
+
+        let last = size - 1;
+        for (x, y) in [(0, 0), (last, 0), (0, last), (last, last)] {
+            field.set(x, y, rng.gen::<f64>());
+        }
+
+        let mut step = last;
+        let mut spread = 1.0;
+
+        while step > 1 {
+            let half = step / 2;
+
+            // diamond step, centers of the squares
+            for y in (half..size).step_by(step) {
+                for x in (half..size).step_by(step) {
+                    let average = (field.get(x - half, y - half) + field.get(x + half, y - half) +
+                                   field.get(x - half, y + half) + field.get(x + half, y + half)) * 0.25;
+                    field.set(x, y, average + (rng.gen::<f64>() - 0.5) * spread);
+                }
+            }
+
+            // square step, edge midpoints
+            for y in (0..size).step_by(half) {
+                let start = if (y / half).is_multiple_of(2) { half } else { 0 };
+
+                for x in (start..size).step_by(step) {
+                    let mut sum = 0.0;
+                    let mut count = 0.0;
+
+                    if x >= half { sum += field.get(x - half, y); count += 1.0; }
+                    if x + half < size { sum += field.get(x + half, y); count += 1.0; }
+                    if y >= half { sum += field.get(x, y - half); count += 1.0; }
+                    if y + half < size { sum += field.get(x, y + half); count += 1.0; }
+
+                    field.set(x, y, sum / count + (rng.gen::<f64>() - 0.5) * spread);
+                }
+            }
+
+            step = half;
+            spread *= roughness;
+        }
+
+        field.normalize();
+        field
+    }
+
+
+    /**
+     * @return the interpolated value at u, v, which are between 0 and 1
+     */
+    pub fn sample(&self, u: f64, v: f64) -> f64 {
+        let last = (self.size - 1) as f64;
+        let fx = u.clamp(0.0, 1.0) * last;
+        let fy = v.clamp(0.0, 1.0) * last;
+
+        let x0 = (fx.floor() as usize).min(self.size - 2);
+        let y0 = (fy.floor() as usize).min(self.size - 2);
+        let tx = fx - x0 as f64;
+        let ty = fy - y0 as f64;
+
+        let top = self.get(x0, y0) * (1.0 - tx) + self.get(x0 + 1, y0) * tx;
+        let bottom = self.get(x0, y0 + 1) * (1.0 - tx) + self.get(x0 + 1, y0 + 1) * tx;
+
+        top * (1.0 - ty) + bottom * ty
+    }
+
+
+    fn get(&self, x: usize, y: usize) -> f64 {
+        self.values[y * self.size + x]
+    }
+
+
+    fn set(&mut self, x: usize, y: usize, value: f64) {
+        self.values[y * self.size + x] = value;
+    }
+
+
+    fn normalize(&mut self) {
+        let min = self.values.iter().cloned().fold(f64::MAX, f64::min);
+        let max = self.values.iter().cloned().fold(f64::MIN, f64::max);
+        let range = if max > min { max - min } else { 1.0 };
+
+        for value in &mut self.values {
+            *value = (*value - min) / range;
+        }
+    }
+}
+
+
+/**
+ * Replaces the contents of the map with a generated landscape. The same config
+ * always produces the same map. The rng is only used to spawn the creatures.
+ */
+pub fn generate_map(map: &mut Map, config: &GeneratorConfig, rng: &mut StdRng) {
+    let mut generator_rng = StdRng::seed_from_u64(config.seed);
+    let map_rng = &mut generator_rng;
+
+    let terrain = HeightField::new(6, config.roughness, map_rng);
+    let clouds = HeightField::new(5, config.roughness, map_rng);
+
+    let transitions = place_transitions(config, map_rng);
+    let populations = place_populations(config, &terrain, &transitions, map_rng);
+
+    let mut clearings: Vec<Vector2<f64>> = transitions.iter().map(|t| t.from).collect();
+    clearings.extend(populations.iter().map(|p| p.center));
+
+    let mut objects = Vec::new();
+    let columns = (config.size[0] / config.cell_size) as usize;
+    let rows = (config.size[1] / config.cell_size) as usize;
+
+    for row in 0..rows {
+        for column in 0..columns {
+            let position = [
+                (column as f64 + map_rng.gen::<f64>()) * config.cell_size,
+                (row as f64 + map_rng.gen::<f64>()) * config.cell_size,
+            ];
+            let u = position[0] / config.size[0];
+            let v = position[1] / config.size[1];
+
+            let level = terrain.sample(u, v);
+
+            if level < GROUND_LEVEL && map_rng.gen::<f64>() < config.ground_density {
+                if let Some(mob) = make_object(map, &config.ground_tiles, MAP_GROUND_LAYER, position, 0.8, 2.0, map_rng) {
+                    objects.push((MAP_GROUND_LAYER, mob));
+                }
+            }
+
+            let chance = (level - DECORATION_LEVEL) / (1.0 - DECORATION_LEVEL) * config.decoration_density;
+            let in_clearing = clearings.iter().any(|c| vec2_square_len(vec2_sub(*c, position)) < CLEARING_RADIUS * CLEARING_RADIUS);
+
+            if level > DECORATION_LEVEL && !in_clearing && map_rng.gen::<f64>() < chance {
+                if let Some(mob) = make_object(map, &config.decoration_tiles, MAP_OBJECT_LAYER, position, 0.2, 0.6, map_rng) {
+                    objects.push((MAP_OBJECT_LAYER, mob));
+                }
+            }
+
+            if clouds.sample(u, v) > CLOUD_LEVEL && map_rng.gen::<f64>() < config.cloud_density {
+                if let Some(mut mob) = make_object(map, &config.cloud_tiles, MAP_CLOUD_LAYER, position, 1.0, 3.0, map_rng) {
+                    mob.visual.blend = BlendMode::Add;
+                    mob.visual.color[3] = 0.2 + map_rng.gen::<f32>() * 0.4;
+                    objects.push((MAP_CLOUD_LAYER, mob));
+                }
+            }
+        }
+    }
+
+    println!("Generated map with seed {}: {} objects, {} populations, {} transitions",
+             config.seed, objects.len(), populations.len(), transitions.len());
+
+    let data = MapData {
+        name: config.name.to_string(),
+        map_image_name: config.map_image_name.to_string(),
+        backdrop_image_name: config.backdrop_image_name.to_string(),
+        layer_info: std::array::from_fn(LayerInfo::default_for),
+        objects,
+        transitions,
+        populations,
+    };
+
+    map.apply_map_data(data, rng);
+}
+
+
+fn make_object(map: &mut Map, tiles: &[usize], layer: usize, position: Vector2<f64>,
+               min_scale: f64, max_scale: f64, rng: &mut StdRng) -> Option<MapObject> {
+    if tiles.is_empty() {
+        return None;
+    }
+
+    let tile_id = tiles[rng.gen_range(0..tiles.len())];
+    let scale = min_scale + rng.gen::<f64>() * (max_scale - min_scale);
+    // generated maps use the default layers
+    let tileset_id = LayerInfo::default_for(layer).tileset_id;
+
+    let mut mob = map.factory.create_mob(tile_id, tileset_id, position, 0.0, scale);
+
+    // slight variations in brightness make repeated tiles look less alike
+    let brightness = 0.85 + rng.gen::<f32>() * 0.15;
+    mob.visual.color = [brightness, brightness, brightness, 1.0];
+
+    Some(mob)
+}
+
+
+/**
+ * Transitions go around the map edges, one side after the other.
+ */
+fn place_transitions(config: &GeneratorConfig, rng: &mut StdRng) -> Vec<MapTransition> {
+    let inset = 300.0;
+    let mut transitions = Vec::new();
+
+    for (i, transition) in config.transitions.iter().enumerate() {
+        let along = 0.2 + rng.gen::<f64>() * 0.6;
+        let from =
+            match i % 4 {
+                0 => [config.size[0] * along, inset],
+                1 => [config.size[0] - inset, config.size[1] * along],
+                2 => [config.size[0] * along, config.size[1] - inset],
+                _ => [inset, config.size[1] * along],
+            };
+
+        transitions.push(MapTransition {
+            from,
+            rad: 120.0,
+            to_map: transition.to_map.to_string(),
+            to_position: transition.to_position,
+            facing: transition.facing,
+        });
+    }
+
+    transitions
+}
+
+
+/**
+ * Creatures gather on open ground, away from the transitions and each other.
+ */
+fn place_populations(config: &GeneratorConfig, terrain: &HeightField,
+                     transitions: &[MapTransition], rng: &mut StdRng) -> Vec<Population> {
+    let mut populations: Vec<Population> = Vec::new();
+
+    if config.creatures.is_empty() {
+        return populations;
+    }
+
+    for _i in 0..config.population_count {
+        for _attempt in 0..20 {
+            let center = [
+                config.size[0] * (0.1 + rng.gen::<f64>() * 0.8),
+                config.size[1] * (0.1 + rng.gen::<f64>() * 0.8),
+            ];
+
+            let open = terrain.sample(center[0] / config.size[0], center[1] / config.size[1]) < DECORATION_LEVEL;
+            let far =
+                transitions.iter().all(|t| vec2_square_len(vec2_sub(t.from, center)) > 600.0 * 600.0) &&
+                populations.iter().all(|p| vec2_square_len(vec2_sub(p.center, center)) > 600.0 * 600.0);
+
+            if open && far {
+                let creature_id = &config.creatures[rng.gen_range(0..config.creatures.len())];
+                let min_count = rng.gen_range(3..6);
+
+                populations.push(Population {
+                    creature_id: creature_id.to_string(),
+                    min_count,
+                    max_count: min_count + rng.gen_range(0..4),
+                    center,
+                    spacing: 40.0,
+                    mobile: true,
+                });
+                break;
+            }
+        }
+    }
+
+    populations
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn test_same_seed_same_map() {
+        let config = GeneratorConfig { seed: 42, ..GeneratorConfig::default() };
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let mut map1 = Map::new("", "", "");
+        generate_map(&mut map1, &config, &mut rng);
+
+        let mut map2 = Map::new("", "", "");
+        generate_map(&mut map2, &config, &mut rng);
+
+        assert_eq!(map1.to_file_content().unwrap(), map2.to_file_content().unwrap());
+        assert!(!map1.layers[MAP_OBJECT_LAYER].is_empty());
+        assert_eq!(map1.transitions.len(), 1);
+    }
+
+
+    #[test]
+    fn test_remove_unknown_tiles() {
+        let mut config = GeneratorConfig { ground_tiles: vec![27, 28], decoration_tiles: vec![16, 500], cloud_tiles: vec![1], ..GeneratorConfig::default() };
+        config.remove_unknown_tiles(|tileset_id, tile_id| tileset_id != MAP_CLOUD_LAYER && tile_id < 100);
+
+        assert_eq!(config.ground_tiles, vec![27, 28]);
+        assert_eq!(config.decoration_tiles, vec![16]);
+        assert!(config.cloud_tiles.is_empty());
+    }
+
+
+    #[test]
+    fn test_height_field_range() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let field = HeightField::new(4, 0.5, &mut rng);
+
+        for i in 0..=20 {
+            let value = field.sample(i as f64 / 20.0, 1.0 - i as f64 / 20.0);
+            assert!((0.0..=1.0).contains(&value));
+        }
+    }
+}
@@ -1,26 +1,49 @@
 use std::collections::HashMap;
 
 use rand::Rng;
+use serde::{Serialize, Deserialize};
 use rand::rngs::StdRng;
 use vecmath::{Vector2, vec2_sub, vec2_square_len};
 
 use crate::map::MapObject;
 use crate::map::MapObjectFactory;
 use crate::map::MobType;
-use crate::map::move_mob;
+use crate::map::{move_mob, move_mob_along_path};
+use crate::pathfinding::find_path;
+use crate::collision::{Blocker, MOVER_RADIUS, is_free};
 use crate::game::fire_projectile;
 use crate::projectile::ProjectileBuilder;
 use crate::SoundPlayer;
+use crate::map_file::{serialize_rounded, serialize_rounded_vec2};
 
 
 pub struct MobGroup {
 
     // Group center x and y - the group should move as a whole
-    center: Vector2<f64>,
+    pub center: Vector2<f64>,
 
     members: Vec<MobGroupMember>,
 }
 
+/**
+ * Describes a group of creatures which is spawned when a map is entered.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Population {
+    pub creature_id: String,
+    pub min_count: i32,
+    pub max_count: i32,
+
+    #[serde(serialize_with = "serialize_rounded_vec2")]
+    pub center: Vector2<f64>,
+
+    // creatures are placed randomly at up to 5 times this distance from the center
+    #[serde(serialize_with = "serialize_rounded")]
+    pub spacing: f64,
+    pub mobile: bool,
+}
+
+
 pub struct MobGroupMember {
     id: u64,
 
@@ -53,7 +76,7 @@ impl MobGroup {
 
     pub fn update(&mut self, player_id: u64, dt: f64, mobs: &mut HashMap<u64, MapObject>, rng: &mut StdRng, 
                   factory: &mut MapObjectFactory, projectile_builder: &mut ProjectileBuilder,
-                  speaker: &mut SoundPlayer) {
+                  speaker: &mut SoundPlayer, blockers: &[Blocker]) {
             
         let player_position = mobs.get(&player_id).unwrap().position;
 
@@ -108,19 +131,25 @@ impl MobGroup {
 
                                 // println!("len={}", len);
 
-                                if len < 100.0 * 100.0 || count >= 5 { break; }
+                                if (len < 100.0 * 100.0 && is_free([x, y], MOVER_RADIUS, blockers)) || count >= 5 { break; }
                             } 
 
+                            let creature = mob.creature.as_ref().unwrap();
+                            let speed = creature.base_speed;
+
                             if count >= 5 {
                                 println!("make {} return from {:?} to group center at {:?}", mob.uid, mob.position, self.center);
                                 x = self.center[0] + 50.0 - rng.gen::<f64>() * 100.0;
                                 y = self.center[1] + 50.0 - rng.gen::<f64>() * 100.0;
-                            }
-
-                            // println!("id=" + creature.id + "moves to " + x + ", " + y);
 
-                            let creature = mob.creature.as_ref().unwrap();
-                            move_mob(mob, [x, y], creature.base_speed);
+                                // the way back might lead around obstacles
+                                let path = find_path(mob.position, [x, y], MOVER_RADIUS, blockers);
+                                move_mob_along_path(mob, path, speed);
+                            }
+                            else {
+                                // println!("id=" + creature.id + "moves to " + x + ", " + y);
+                                move_mob(mob, [x, y], speed, blockers);
+                            }
                             
                             member.action_countdown = 3.0 + rng.gen::<f64>() * 2.0;
                         }
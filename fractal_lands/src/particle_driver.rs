@@ -1,3 +1,9 @@
+use rand::Rng;
+use rand::rngs::StdRng;
+use serde::{Serialize, Deserialize};
+
+use crate::map::MAP_CLOUD_LAYER;
+
 const PMAX: usize = 1024;
 
 pub struct Particle 
@@ -31,17 +37,56 @@ const NEW_PARTICLE: Particle = Particle {
 };
 
 
+/**
+ * Settings of a particle source which keeps spawning particles. These
+ * are stored with the map object in the map file.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmitterSettings {
+    // tiles of the particles, one is chosen randomly for each particle
+    pub spawn_ids: Vec<usize>,
+    pub tileset_id: usize,
+
+    // new particles per second
+    pub rate: f64,
+
+    // spawn position relative to the object, and ranges for the velocity
+    pub position: [f64; 3],
+    pub velocity_min: [f64; 3],
+    pub velocity_max: [f64; 3],
+
+    // range for the lifetime in seconds
+    pub lifetime: [f64; 2],
+    pub color: [f32; 3],
+}
+
+
+impl Default for EmitterSettings {
+    fn default() -> Self {
+        EmitterSettings {
+            spawn_ids: (52 ..= 59).collect(),
+            tileset_id: MAP_CLOUD_LAYER,
+            rate: 30.0,
+            position: [0.0, -400.0, 0.0],
+            velocity_min: [0.0, 0.0, 0.0],
+            velocity_max: [0.0, 0.0, 0.0],
+            lifetime: [0.1, 0.1],
+            color: [0.7, 0.75, 0.9],
+        }
+    }
+}
+
+
 pub struct ParticleDriver {
     start_search_mark: usize,
     last_particle_mark: usize,
 
-    particles: [Particle; PMAX],
+    // boxed, so that map objects stay small enough to be moved around cheaply
+    particles: Box<[Particle; PMAX]>,
 
     // for auto spawning
-    pub spawn_ids: Vec<usize>,
-
-    // chance to spawn a new partile per second
-    pub spawn_chance: f64,
+    emitter: Option<EmitterSettings>,
 
     pub spawn_tile_set: usize, 
 }
@@ -53,13 +98,89 @@ impl ParticleDriver {
         ParticleDriver {
             start_search_mark: 0,
             last_particle_mark: 0,
-            particles: [NEW_PARTICLE; PMAX],
+            particles: Box::new([NEW_PARTICLE; PMAX]),
 
-            spawn_ids: Vec::new(),
-            spawn_chance: 0.0,
+            emitter: None,
             spawn_tile_set: 1,
         }        
     }
+
+
+    /**
+     * @return a driver with the same spawn settings, but without any particles
+     */
+    pub fn copy_settings(&self) -> ParticleDriver {
+        let mut driver = ParticleDriver::new();
+        driver.emitter = self.emitter.clone();
+        driver.spawn_tile_set = self.spawn_tile_set;
+
+        driver
+    }
+
+
+    /**
+     * @return a driver which spawns particles with the given settings
+     */
+    pub fn with_emitter(emitter: EmitterSettings) -> ParticleDriver {
+        let mut driver = ParticleDriver::new();
+        driver.set_emitter(Some(emitter));
+
+        driver
+    }
+
+
+    pub fn emitter(&self) -> Option<&EmitterSettings> {
+        self.emitter.as_ref()
+    }
+
+
+    /**
+     * Particles of the old settings stay until they expire. The particle tiles
+     * are taken from the tileset of the emitter.
+     */
+    pub fn set_emitter(&mut self, emitter: Option<EmitterSettings>) {
+        if let Some(settings) = &emitter {
+            self.spawn_tile_set = settings.tileset_id;
+        }
+        self.emitter = emitter;
+    }
+
+
+    pub fn has_no_emitter(&self) -> bool {
+        self.emitter.is_none()
+    }
+
+
+    /**
+     * Spawns the particles of the emitter for the passed time.
+     */
+    pub fn emit(&mut self, dt: f64, rng: &mut StdRng) {
+        let emitter = match self.emitter.take() {
+            None => return,
+            Some(emitter) => emitter,
+        };
+
+        let len = emitter.spawn_ids.len();
+
+        if len > 0 {
+            // the fraction of a particle is spawned by chance
+            let expected = emitter.rate * dt;
+            let count = expected.floor() as usize + if rng.gen::<f64>() < expected.fract() { 1 } else { 0 };
+
+            for _i in 0..count {
+                let spark = emitter.spawn_ids[rng.gen_range(0..len)];
+                let v: [f64; 3] = std::array::from_fn(|i| {
+                    emitter.velocity_min[i] + (emitter.velocity_max[i] - emitter.velocity_min[i]) * rng.gen::<f64>()
+                });
+                let lifetime = emitter.lifetime[0] + (emitter.lifetime[1] - emitter.lifetime[0]) * rng.gen::<f64>();
+                let p = &emitter.position;
+
+                self.add_particle(p[0], p[1], p[2], v[0], v[1], v[2], lifetime, spark, emitter.color);
+            }
+        }
+
+        self.emitter = Some(emitter);
+    }
     
 
     pub fn add_particle(&mut self, x: f64, y: f64, z: f64, xv: f64, yv: f64, zv: f64, lifetime: f64, tex_id: usize, color: [f32; 3]) -> bool {
@@ -153,3 +274,65 @@ impl ParticleDriver {
         return self.last_particle_mark > 0;
     }
 }
+
+
+/**
+ * Map files only store the emitter settings of a particle driver.
+ */
+pub mod emitter_serde {
+    use serde::{Serialize, Deserialize, Serializer, Deserializer};
+    use super::{ParticleDriver, EmitterSettings};
+
+
+    pub fn serialize<S: Serializer>(driver: &ParticleDriver, serializer: S) -> Result<S::Ok, S::Error> {
+        driver.emitter.serialize(serializer)
+    }
+
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ParticleDriver, D::Error> {
+        let emitter = Option::<EmitterSettings>::deserialize(deserializer)?;
+        let mut driver = ParticleDriver::new();
+        driver.set_emitter(emitter);
+
+        Ok(driver)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+
+    #[test]
+    fn test_emitter() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let settings = EmitterSettings {
+            rate: 100.0,
+            velocity_min: [10.0, 0.0, 5.0],
+            velocity_max: [20.0, 0.0, 5.0],
+            lifetime: [1.0, 2.0],
+            tileset_id: 4,
+            ..EmitterSettings::default()
+        };
+        let mut driver = ParticleDriver::with_emitter(settings);
+        assert_eq!(driver.spawn_tile_set, 4);
+
+        driver.emit(0.5, &mut rng);
+
+        driver.for_each_particle(|particles, last_particle_mark| {
+            let active: Vec<&Particle> = particles[0..last_particle_mark].iter().filter(|p| p.active).collect();
+            assert!(active.len() >= 50 && active.len() <= 51);
+
+            for p in active {
+                assert!(p.xvel >= 10.0 && p.xvel <= 20.0);
+                assert_eq!(p.zvel, 5.0);
+                assert!(p.lifetime >= 1.0 && p.lifetime <= 2.0);
+                assert!((52 ..= 59).contains(&p.tex_id));
+            }
+        });
+
+        assert_eq!(driver.copy_settings().emitter(), driver.emitter());
+    }
+}
@@ -0,0 +1,325 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use vecmath::{Vector2, vec2_add, vec2_sub, vec2_scale, vec2_len};
+
+use crate::collision::{Blocker, is_free};
+
+
+// edge length of the navigation grid cells, in world coordinates
+pub const NAV_CELL_SIZE: f64 = 16.0;
+
+// the grid reaches this far beyond start and destination, so paths can go around obstacles
+pub const NAV_MARGIN: f64 = 256.0;
+
+// step costs, diagonal steps are ~sqrt(2) times as long
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+
+
+/**
+ * A grid of walkable and blocked cells, covering a rectangular area of the map.
+ */
+pub struct NavGrid {
+    origin: Vector2<f64>,
+    cell_size: f64,
+    width: usize,
+    height: usize,
+    blocked: Vec<bool>,
+}
+
+
+impl NavGrid {
+
+    /**
+     * Builds a grid for the area between the two corners. A cell is blocked if a mover
+     * of the given radius cannot stand in its center.
+     */
+    pub fn new(corner1: Vector2<f64>, corner2: Vector2<f64>, cell_size: f64,
+               mover_radius: f64, blockers: &[Blocker]) -> NavGrid {
+
+        let origin = [corner1[0].min(corner2[0]), corner1[1].min(corner2[1])];
+        let width = ((corner1[0] - corner2[0]).abs() / cell_size).ceil() as usize + 1;
+        let height = ((corner1[1] - corner2[1]).abs() / cell_size).ceil() as usize + 1;
+
+        let mut grid = NavGrid {
+            origin,
+            cell_size,
+            width,
+            height,
+            blocked: vec![false; width * height],
+        };
+
+        for y in 0..height {
+            for x in 0..width {
+                let center = grid.cell_center(x, y);
+                grid.blocked[y * width + x] = !is_free(center, mover_radius, blockers);
+            }
+        }
+
+        grid
+    }
+
+
+    pub fn is_blocked(&self, x: usize, y: usize) -> bool {
+        self.blocked[y * self.width + x]
+    }
+
+
+    fn cell_center(&self, x: usize, y: usize) -> Vector2<f64> {
+        [self.origin[0] + (x as f64 + 0.5) * self.cell_size,
+         self.origin[1] + (y as f64 + 0.5) * self.cell_size]
+    }
+
+
+    fn cell_of(&self, position: Vector2<f64>) -> (usize, usize) {
+        let x = ((position[0] - self.origin[0]) / self.cell_size).floor().max(0.0) as usize;
+        let y = ((position[1] - self.origin[1]) / self.cell_size).floor().max(0.0) as usize;
+
+        (x.min(self.width - 1), y.min(self.height - 1))
+    }
+
+
+    /**
+     * @return the free cell which is closest to the given cell
+     */
+    fn nearest_free_cell(&self, cell: (usize, usize)) -> Option<(usize, usize)> {
+        let mut best = None;
+        let mut best_distance = usize::MAX;
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if !self.is_blocked(x, y) {
+                    let dx = x.abs_diff(cell.0);
+                    let dy = y.abs_diff(cell.1);
+                    let distance = dx * dx + dy * dy;
+
+                    if distance < best_distance {
+                        best_distance = distance;
+                        best = Some((x, y));
+                    }
+                }
+            }
+        }
+
+        best
+    }
+
+
+    /**
+     * A* search from the start to the goal cell. The start cell is always considered
+     * walkable, so movers which are stuck in an obstacle can leave it.
+     *
+     * @return the cells of the path, including start and goal, or None if the goal
+     *         cannot be reached
+     */
+    fn search(&self, start: (usize, usize), goal: (usize, usize)) -> Option<Vec<(usize, usize)>> {
+        let size = self.width * self.height;
+        let start_index = start.1 * self.width + start.0;
+        let goal_index = goal.1 * self.width + goal.0;
+
+        let mut cost = vec![u32::MAX; size];
+        let mut came_from = vec![usize::MAX; size];
+        let mut open = BinaryHeap::new();
+
+        cost[start_index] = 0;
+        open.push(Reverse((self.estimate(start, goal), start_index)));
+
+        while let Some(Reverse((_estimate, index))) = open.pop() {
+            if index == goal_index {
+                return Some(self.backtrack(&came_from, start_index, goal_index));
+            }
+
+            let x = (index % self.width) as i64;
+            let y = (index / self.width) as i64;
+
+            for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1), (-1, -1), (1, -1), (-1, 1), (1, 1)] {
+                let nx = x + dx;
+                let ny = y + dy;
+
+                if nx < 0 || ny < 0 || nx >= self.width as i64 || ny >= self.height as i64 {
+                    continue;
+                }
+
+                if self.is_blocked(nx as usize, ny as usize) {
+                    continue;
+                }
+
+                let diagonal = dx != 0 && dy != 0;
+
+                // don't cut corners of obstacles
+                if diagonal && (self.is_blocked(nx as usize, y as usize) || self.is_blocked(x as usize, ny as usize)) {
+                    continue;
+                }
+
+                let step = if diagonal { DIAGONAL_COST } else { STRAIGHT_COST };
+                let next = ny as usize * self.width + nx as usize;
+                let next_cost = cost[index] + step;
+
+                if next_cost < cost[next] {
+                    cost[next] = next_cost;
+                    came_from[next] = index;
+
+                    let estimate = next_cost + self.estimate((nx as usize, ny as usize), goal);
+                    open.push(Reverse((estimate, next)));
+                }
+            }
+        }
+
+        None
+    }
+
+
+    // octile distance, never overestimates the remaining cost
+    fn estimate(&self, from: (usize, usize), to: (usize, usize)) -> u32 {
+        let dx = from.0.abs_diff(to.0) as u32;
+        let dy = from.1.abs_diff(to.1) as u32;
+
+        STRAIGHT_COST * dx.max(dy) + (DIAGONAL_COST - STRAIGHT_COST) * dx.min(dy)
+    }
+
+
+    fn backtrack(&self, came_from: &[usize], start_index: usize, goal_index: usize) -> Vec<(usize, usize)> {
+        let mut cells = Vec::new();
+        let mut index = goal_index;
+
+        while index != start_index {
+            cells.push((index % self.width, index / self.width));
+            index = came_from[index];
+        }
+
+        cells.push((start_index % self.width, start_index / self.width));
+        cells.reverse();
+
+        cells
+    }
+}
+
+
+/**
+ * Finds a way around the blockers. If the destination is blocked or cannot be
+ * reached, the path leads to the closest reachable point instead.
+ *
+ * @return the waypoints to visit, not including the start position
+ */
+pub fn find_path(from: Vector2<f64>, to: Vector2<f64>, mover_radius: f64, blockers: &[Blocker]) -> Vec<Vector2<f64>> {
+
+    // the simple case, nothing in the way
+    if is_line_free(from, to, mover_radius, blockers) {
+        return vec![to];
+    }
+
+    let corner1 = [from[0].min(to[0]) - NAV_MARGIN, from[1].min(to[1]) - NAV_MARGIN];
+    let corner2 = [from[0].max(to[0]) + NAV_MARGIN, from[1].max(to[1]) + NAV_MARGIN];
+    let grid = NavGrid::new(corner1, corner2, NAV_CELL_SIZE, mover_radius, blockers);
+
+    let start = grid.cell_of(from);
+    let mut goal = grid.cell_of(to);
+    let mut destination = to;
+
+    if grid.is_blocked(goal.0, goal.1) {
+        match grid.nearest_free_cell(goal) {
+            None => return Vec::new(),
+            Some(cell) => {
+                goal = cell;
+                destination = grid.cell_center(cell.0, cell.1);
+            }
+        }
+    }
+
+    match grid.search(start, goal) {
+        None => {
+            println!("No path found from {:?} to {:?}", from, to);
+            Vec::new()
+        },
+        Some(cells) => {
+            let mut waypoints: Vec<Vector2<f64>> = cells.iter().map(|cell| grid.cell_center(cell.0, cell.1)).collect();
+
+            // the grid cells are only an approximation of start and destination
+            waypoints[0] = from;
+            let last = waypoints.len() - 1;
+            waypoints[last] = destination;
+
+            smooth_path(&waypoints, mover_radius, blockers)
+        }
+    }
+}
+
+
+/**
+ * Drops all waypoints which can be skipped by going straight to a later one.
+ */
+fn smooth_path(waypoints: &[Vector2<f64>], mover_radius: f64, blockers: &[Blocker]) -> Vec<Vector2<f64>> {
+    let mut result = Vec::new();
+    let mut current = 0;
+
+    while current < waypoints.len() - 1 {
+        let mut next = waypoints.len() - 1;
+
+        while next > current + 1 && !is_line_free(waypoints[current], waypoints[next], mover_radius, blockers) {
+            next -= 1;
+        }
+
+        result.push(waypoints[next]);
+        current = next;
+    }
+
+    result
+}
+
+
+/**
+ * @return true if a mover can go straight from start to destination. Blockers which
+ *         already overlap the start are ignored.
+ */
+fn is_line_free(from: Vector2<f64>, to: Vector2<f64>, mover_radius: f64, blockers: &[Blocker]) -> bool {
+    let relevant: Vec<Blocker> = blockers.iter().filter(|b| is_free(from, mover_radius, std::slice::from_ref(b))).cloned().collect();
+
+    let direction = vec2_sub(to, from);
+    let samples = (vec2_len(direction) / (NAV_CELL_SIZE * 0.25)).ceil() as usize;
+
+    for i in 1 ..= samples {
+        let point = vec2_add(from, vec2_scale(direction, i as f64 / samples as f64));
+
+        if !is_free(point, mover_radius, &relevant) {
+            return false;
+        }
+    }
+
+    true
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn test_path_around_blocker() {
+        let blockers = [Blocker { position: [100.0, 0.0], radius: 50.0 }];
+
+        let path = find_path([0.0, 0.0], [200.0, 0.0], 10.0, &blockers);
+
+        assert!(path.len() >= 2);
+        assert_eq!(*path.last().unwrap(), [200.0, 0.0]);
+
+        let mut position = [0.0, 0.0];
+        for waypoint in path {
+            assert!(is_line_free(position, waypoint, 10.0, &blockers));
+            position = waypoint;
+        }
+    }
+
+
+    #[test]
+    fn test_blocked_destination() {
+        let blockers = [Blocker { position: [200.0, 0.0], radius: 50.0 }];
+
+        let path = find_path([0.0, 0.0], [200.0, 0.0], 10.0, &blockers);
+        let end = *path.last().unwrap();
+
+        assert!(is_free(end, 10.0, &blockers));
+        assert!(vec2_len(vec2_sub(end, [200.0, 0.0])) < 60.0 + NAV_CELL_SIZE * 2.0);
+    }
+}
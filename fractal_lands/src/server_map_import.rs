@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use vecmath::Vector2;
+
+use crate::map::{Map, MapData, MapLoadError, MapTransition, LayerInfo};
+use crate::map::{MAP_GROUND_LAYER, MAP_OBJECT_LAYER, MAP_CLOUD_LAYER};
+use crate::gl_support::BlendMode;
+use crate::try_parse_rgba;
+
+
+pub const SERVER_MAP_PATH: &str = "../tiny_places_server/maps";
+pub const SERVER_TRANSITIONS_FILE: &str = "../tiny_places_server/src/tinyplaces/resources/transitions.csv";
+pub const CLIENT_RESOURCE_PATH: &str = "../tiny_places_client/resources";
+
+// The multiplayer client draws the map image unscaled, 24 pixels down from the
+// top of the window. Here map images are drawn at twice the size, and world y
+// coordinates are twice the screen y coordinates.
+const SERVER_MAP_TOP: f64 = 24.0;
+const SERVER_SCALE: f64 = 2.0;
+
+// server maps have no catchment area for transitions
+const SERVER_TRANSITION_RADIUS: f64 = 120.0;
+
+
+/**
+ * Height and blend mode of a tile, as far as the importer needs to know
+ */
+struct ServerTile {
+    height: f64,
+    blend: BlendMode,
+}
+
+
+/**
+ * Converts a map of the multiplayer server to the current map format.
+ * The source is the name of the map in the server maps directory, without
+ * the .txt extension. The result is saved to the maps directory.
+ */
+pub fn import_server_map(source: &str, destination: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut map = Map::new("", "", "");
+
+    // populations are only spawned, not saved, so the seed doesn't matter
+    let mut rng = StdRng::seed_from_u64(0);
+
+    load_server_map(&mut map, source, &mut rng)?;
+    map.save(destination)?;
+
+    Ok(())
+}
+
+
+/**
+ * Replaces the contents of the map with the objects and transitions of a server map.
+ */
+pub fn load_server_map(map: &mut Map, source: &str, rng: &mut StdRng) -> Result<(), MapLoadError> {
+    let filename = source.to_string() + ".txt";
+    let path = Path::new(SERVER_MAP_PATH).join(&filename);
+
+    let content = std::fs::read_to_string(&path)
+        .map_err(|error| MapLoadError::Io { file: filename.to_string(), error })?;
+
+    let mut lines = content.lines();
+    let version = lines.next().unwrap_or("");
+
+    if version != "v10" {
+        return Err(MapLoadError::UnexpectedLine {
+            file: filename,
+            line: 1,
+            expected: "v10".to_string(),
+            found: version.to_string()
+        });
+    }
+
+    let name = lines.next().unwrap_or("").to_string();
+    let map_image_base = lines.next().unwrap_or("").to_string();
+    let map_image_name = map_image_base + ".png";
+
+    if !Path::new("resources/map").join(&map_image_name).exists() {
+        println!("Warning: map image {} is missing in resources/map", map_image_name);
+    }
+
+    let tilesets = [
+        read_server_tiles("grounds"),
+        read_server_tiles("objects"),
+        read_server_tiles("clouds"),
+    ];
+
+    let mut objects = Vec::new();
+
+    for (index, line) in lines.enumerate() {
+        let line_no = index + 4;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let invalid = |field: &str, value: &str| MapLoadError::InvalidField {
+            file: filename.to_string(),
+            line: line_no,
+            field: field.to_string(),
+            value: value.to_string(),
+        };
+
+        let parts: Vec<&str> = line.split(",").collect();
+        if parts.len() != 6 {
+            return Err(MapLoadError::MissingField { file: filename.to_string(), line: line_no, field: "color".to_string() });
+        }
+
+        let layer =
+            match parts[0].trim() {
+                "1" => MAP_GROUND_LAYER,
+                "3" => MAP_OBJECT_LAYER,
+                "5" => MAP_CLOUD_LAYER,
+                _ => return Err(invalid("layer", parts[0])),
+            };
+
+        let tile_id = parts[1].trim().parse::<usize>().map_err(|_| invalid("tile id", parts[1]))?;
+        let x = parts[2].trim().parse::<f64>().map_err(|_| invalid("x", parts[2]))?;
+        let y = parts[3].trim().parse::<f64>().map_err(|_| invalid("y", parts[3]))?;
+        let scale = parts[4].trim().parse::<f64>().map_err(|_| invalid("scale", parts[4]))?;
+        let color = try_parse_rgba(parts[5]).ok_or_else(|| invalid("color", parts[5]))?;
+
+        let tileset_id = map.layer_info[layer].tileset_id;
+        let (height, blend) =
+            match tilesets[layer].get(&tile_id) {
+                None => {
+                    println!("Warning: {}:{}: tile {} is unknown, skipping the object", filename, line_no, tile_id);
+                    continue;
+                },
+                Some(tile) => (tile.height, tile.blend),
+            };
+
+        let mut mob = map.factory.create_mob(tile_id, tileset_id, to_world_pos([x, y]), height, scale * SERVER_SCALE);
+        mob.visual.color = color;
+        mob.visual.blend = blend;
+
+        objects.push((layer, mob));
+    }
+
+    let transitions = read_server_transitions(&name);
+
+    println!("Imported server map '{}' with {} objects and {} transitions", name, objects.len(), transitions.len());
+
+    let data = MapData {
+        name,
+        map_image_name,
+        backdrop_image_name: map.backdrop_image_name.to_string(),
+        layer_info: std::array::from_fn(LayerInfo::default_for),
+        objects,
+        transitions,
+        populations: Vec::new(),
+    };
+
+    map.apply_map_data(data, rng);
+
+    Ok(())
+}
+
+
+fn to_world_pos(server_pos: Vector2<f64>) -> Vector2<f64> {
+    [server_pos[0] * SERVER_SCALE, (server_pos[1] - SERVER_MAP_TOP) * SERVER_SCALE * 2.0]
+}
+
+
+/**
+ * The server keeps all transitions in one table, keyed by the display name
+ * of the map they start from. Destinations are the server map file names,
+ * which become .map files here.
+ */
+fn read_server_transitions(map_name: &str) -> Vec<MapTransition> {
+    let mut transitions = Vec::new();
+
+    let content =
+        match std::fs::read_to_string(SERVER_TRANSITIONS_FILE) {
+            Ok(content) => content,
+            Err(error) => {
+                println!("Warning: cannot read {}: {}", SERVER_TRANSITIONS_FILE, error);
+                return transitions;
+            }
+        };
+
+    // skip the column titles
+    for line in content.lines().skip(1) {
+        let parts: Vec<&str> = line.split(",").map(|p| p.trim()).collect();
+
+        if parts.len() < 9 || parts[2] != map_name {
+            continue;
+        }
+
+        let numbers: Vec<Option<f64>> = [3, 4, 6, 7].iter().map(|i| parts[*i].parse::<f64>().ok()).collect();
+        let facing = parts[8].parse::<usize>().ok();
+
+        match (numbers[0], numbers[1], numbers[2], numbers[3], facing) {
+            (Some(from_x), Some(from_y), Some(to_x), Some(to_y), Some(facing)) => {
+                transitions.push(MapTransition {
+                    from: to_world_pos([from_x, from_y]),
+                    rad: SERVER_TRANSITION_RADIUS,
+                    to_map: parts[5].to_string() + ".map",
+                    to_position: to_world_pos([to_x, to_y]),
+                    facing,
+                });
+            },
+            _ => {
+                println!("Warning: skipping malformed transition '{}'", line);
+            }
+        }
+    }
+
+    transitions
+}
+
+
+/**
+ * Reads foot heights and blend modes from a tile catalog of the multiplayer
+ * client. The tiles are not loaded as textures, so this works without a window.
+ */
+fn read_server_tiles(set: &str) -> HashMap<usize, ServerTile> {
+    let mut tiles = HashMap::new();
+    let path = Path::new(CLIENT_RESOURCE_PATH).join(set).join("map_objects.tica");
+
+    let content =
+        match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(error) => {
+                println!("Warning: cannot read {:?}: {}", path, error);
+                return tiles;
+            }
+        };
+
+    let lines: Vec<&str> = content.lines().collect();
+
+    for (start, line) in lines.iter().enumerate() {
+        if *line == "Tile Description" && start + 12 < lines.len() {
+            let id = lines[start + 2].parse::<usize>();
+            let height = lines[start + 5].split(" ").nth(1).and_then(|h| h.parse::<f64>().ok());
+
+            // the client marks additive tiles with a tag
+            let blend = if lines[start + 12].contains("mode=add") { BlendMode::Add } else { BlendMode::Blend };
+
+            if let (Ok(id), Some(height)) = (id, height) {
+                tiles.insert(id, ServerTile { height, blend });
+            }
+        }
+    }
+
+    tiles
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::MobType;
+
+
+    #[test]
+    fn test_import_lobby() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut map = Map::new("", "", "");
+
+        load_server_map(&mut map, "lobby", &mut rng).unwrap();
+
+        assert_eq!(map.name, "Lobby");
+        assert_eq!(map.map_image_name, "map_soft_grass.png");
+        assert_eq!(map.transitions.len(), 3);
+        assert_eq!(map.transitions[0].to_map, "wasteland_and_pond.map");
+
+        let decorations = map.layers[MAP_OBJECT_LAYER].values().filter(|mob| mob.mob_type == MobType::MapObject).count();
+        assert!(decorations > 0);
+        assert!(!map.layers[MAP_GROUND_LAYER].is_empty());
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+
+use vecmath::Vector2;
+
+
+/**
+ * A uniform grid over map positions. Each object is kept in the cell its
+ * position falls into, so that queries only need to look at the cells
+ * which overlap the search area.
+ */
+pub struct SpatialIndex {
+    cell_size: f64,
+    cells: HashMap<(i32, i32), Vec<u64>>,
+
+    // last known position and cell of each object
+    entries: HashMap<u64, (Vector2<f64>, (i32, i32))>,
+}
+
+
+impl SpatialIndex {
+
+    pub fn new(cell_size: f64) -> SpatialIndex {
+        SpatialIndex {
+            cell_size,
+            cells: HashMap::new(),
+            entries: HashMap::new(),
+        }
+    }
+
+
+    /**
+     * Adds an object, or updates its position if it is already known.
+     */
+    pub fn insert(&mut self, uid: u64, position: Vector2<f64>) {
+        let cell = self.cell_of(position);
+
+        match self.entries.get_mut(&uid) {
+            None => {
+                self.entries.insert(uid, (position, cell));
+                self.cells.entry(cell).or_default().push(uid);
+            },
+            Some(entry) => {
+                let old_cell = entry.1;
+                *entry = (position, cell);
+
+                if old_cell != cell {
+                    self.remove_from_cell(uid, old_cell);
+                    self.cells.entry(cell).or_default().push(uid);
+                }
+            }
+        }
+    }
+
+
+    pub fn remove(&mut self, uid: u64) {
+        if let Some((_position, cell)) = self.entries.remove(&uid) {
+            self.remove_from_cell(uid, cell);
+        }
+    }
+
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.entries.clear();
+    }
+
+
+    /**
+     * @return the object closest to the given position, if there is one within the search radius
+     */
+    pub fn nearest(&self, position: &Vector2<f64>, search_radius: f64, ignore_uid: u64) -> Option<u64> {
+        let mut distance = search_radius * search_radius;
+        let mut result = None;
+
+        let min = [position[0] - search_radius, position[1] - search_radius];
+        let max = [position[0] + search_radius, position[1] + search_radius];
+
+        self.for_each_in_cells(min, max, |uid, pos| {
+            let dx = pos[0] - position[0];
+            let dy = pos[1] - position[1];
+            let d2 = dx * dx + dy * dy;
+
+            if uid != ignore_uid {
+                // equal distances are resolved by uid, to get the same result each time
+                let closer = match result {
+                    None => d2 < distance,
+                    Some(best) => d2 < distance || (d2 == distance && uid < best),
+                };
+
+                if closer {
+                    distance = d2;
+                    result = Some(uid);
+                }
+            }
+        });
+
+        result
+    }
+
+
+    /**
+     * @return all objects within the given distance of the position
+     */
+    pub fn in_radius(&self, position: &Vector2<f64>, radius: f64) -> Vec<u64> {
+        let mut result = Vec::new();
+        let r2 = radius * radius;
+
+        let min = [position[0] - radius, position[1] - radius];
+        let max = [position[0] + radius, position[1] + radius];
+
+        self.for_each_in_cells(min, max, |uid, pos| {
+            let dx = pos[0] - position[0];
+            let dy = pos[1] - position[1];
+
+            if dx * dx + dy * dy <= r2 {
+                result.push(uid);
+            }
+        });
+
+        result
+    }
+
+
+    /**
+     * @return all objects inside the rectangle spanned by the two corners
+     */
+    pub fn in_rect(&self, corner_1: &Vector2<f64>, corner_2: &Vector2<f64>) -> Vec<u64> {
+        let mut result = Vec::new();
+
+        let min = [corner_1[0].min(corner_2[0]), corner_1[1].min(corner_2[1])];
+        let max = [corner_1[0].max(corner_2[0]), corner_1[1].max(corner_2[1])];
+
+        self.for_each_in_cells(min, max, |uid, pos| {
+            if pos[0] >= min[0] && pos[0] <= max[0] && pos[1] >= min[1] && pos[1] <= max[1] {
+                result.push(uid);
+            }
+        });
+
+        result
+    }
+
+
+    fn for_each_in_cells<F>(&self, min: Vector2<f64>, max: Vector2<f64>, mut func: F) 
+        where F: FnMut(u64, &Vector2<f64>) {
+
+        let (x1, y1) = self.cell_of(min);
+        let (x2, y2) = self.cell_of(max);
+
+        for y in y1 ..= y2 {
+            for x in x1 ..= x2 {
+                if let Some(uids) = self.cells.get(&(x, y)) {
+                    for uid in uids {
+                        let (pos, _cell) = &self.entries[uid];
+                        func(*uid, pos);
+                    }
+                }
+            }
+        }
+    }
+
+
+    fn cell_of(&self, position: Vector2<f64>) -> (i32, i32) {
+        ((position[0] / self.cell_size).floor() as i32, (position[1] / self.cell_size).floor() as i32)
+    }
+
+
+    fn remove_from_cell(&mut self, uid: u64, cell: (i32, i32)) {
+        if let Some(uids) = self.cells.get_mut(&cell) {
+            uids.retain(|id| *id != uid);
+
+            if uids.is_empty() {
+                self.cells.remove(&cell);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn test_nearest_follows_moves() {
+        let mut index = SpatialIndex::new(100.0);
+        index.insert(1, [10.0, 10.0]);
+        index.insert(2, [500.0, 500.0]);
+
+        assert_eq!(index.nearest(&[0.0, 0.0], 50.0, 0), Some(1));
+        assert_eq!(index.nearest(&[0.0, 0.0], 50.0, 1), None);
+
+        // move object 2 into a different cell, next to the origin
+        index.insert(2, [-5.0, 0.0]);
+        assert_eq!(index.nearest(&[0.0, 0.0], 50.0, 0), Some(2));
+        assert_eq!(index.in_radius(&[500.0, 500.0], 50.0), Vec::<u64>::new());
+
+        index.remove(2);
+        assert_eq!(index.nearest(&[0.0, 0.0], 50.0, 0), Some(1));
+        assert_eq!(index.in_radius(&[0.0, 0.0], 50.0), vec![1]);
+    }
+
+
+    #[test]
+    fn test_radius_and_rect_queries() {
+        let mut index = SpatialIndex::new(64.0);
+
+        for i in 0..10 {
+            index.insert(i, [i as f64 * 50.0, 0.0]);
+        }
+
+        let mut found = index.in_radius(&[100.0, 0.0], 60.0);
+        found.sort();
+        assert_eq!(found, vec![1, 2, 3]);
+
+        let mut found = index.in_rect(&[260.0, 10.0], &[120.0, -10.0]);
+        found.sort();
+        assert_eq!(found, vec![3, 4, 5]);
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+use ron::ser::PrettyConfig;
+
+
+// the palette is kept in the maps directory, next to the generator config
+pub const PALETTE_FILE: &str = "tile_palette.ron";
+
+// length of the "recently used" strip of the tile selector
+pub const RECENT_COUNT: usize = 5;
+
+
+/**
+ * Recently used and favorite tiles of the map editor, by tileset id.
+ * Both lists are saved, so they are kept between sessions.
+ */
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct TilePalette {
+    recent: HashMap<usize, Vec<usize>>,
+    favorites: HashMap<usize, Vec<usize>>,
+}
+
+
+impl TilePalette {
+
+    /**
+     * Reads the palette file from the maps directory. A missing or broken
+     * file gives an empty palette.
+     */
+    pub fn load() -> TilePalette {
+        let path = std::path::Path::new("maps").join(PALETTE_FILE);
+
+        match std::fs::read_to_string(path) {
+            Ok(content) => {
+                ron::from_str(&content).unwrap_or_else(|error| {
+                    println!("Error in {}: {}", PALETTE_FILE, error);
+                    TilePalette::default()
+                })
+            },
+            Err(_) => TilePalette::default()
+        }
+    }
+
+
+    pub fn save(&self) {
+        let path = std::path::Path::new("maps").join(PALETTE_FILE);
+        let result = ron::ser::to_string_pretty(self, PrettyConfig::default())
+            .map_err(|error| error.to_string())
+            .and_then(|content| std::fs::write(path, content).map_err(|error| error.to_string()));
+
+        if let Err(error) = result {
+            println!("Cannot save {}: {}", PALETTE_FILE, error);
+        }
+    }
+
+
+    pub fn recent(&self, tileset_id: usize) -> &[usize] {
+        self.recent.get(&tileset_id).map_or(&[], |list| list.as_slice())
+    }
+
+
+    pub fn favorites(&self, tileset_id: usize) -> &[usize] {
+        self.favorites.get(&tileset_id).map_or(&[], |list| list.as_slice())
+    }
+
+
+    /**
+     * Puts the tile in front of the recently used tiles.
+     */
+    pub fn use_tile(&mut self, tileset_id: usize, tile_id: usize) {
+        let list = self.recent.entry(tileset_id).or_default();
+
+        list.retain(|id| *id != tile_id);
+        list.insert(0, tile_id);
+        list.truncate(RECENT_COUNT);
+    }
+
+
+    /**
+     * @return true if the tile is a favorite now
+     */
+    pub fn toggle_favorite(&mut self, tileset_id: usize, tile_id: usize) -> bool {
+        let list = self.favorites.entry(tileset_id).or_default();
+
+        if list.contains(&tile_id) {
+            list.retain(|id| *id != tile_id);
+            false
+        }
+        else {
+            list.push(tile_id);
+            true
+        }
+    }
+}
+
+
+/**
+ * A tile name matches, if it contains all words of the filter. Case is ignored.
+ */
+pub fn matches_filter(name: &str, filter: &str) -> bool {
+    let name = name.to_lowercase();
+
+    filter.to_lowercase().split_whitespace().all(|word| name.contains(word))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn test_palette() {
+        let mut palette = TilePalette::default();
+
+        for id in [3, 4, 5, 6, 7, 8, 4] {
+            palette.use_tile(1, id);
+        }
+
+        assert_eq!(palette.recent(1), &[4, 8, 7, 6, 5]);
+        assert!(palette.recent(2).is_empty());
+
+        assert!(palette.toggle_favorite(1, 12));
+        assert!(palette.toggle_favorite(1, 10));
+        assert!(!palette.toggle_favorite(1, 12));
+        assert_eq!(palette.favorites(1), &[10]);
+
+        let text = ron::to_string(&palette).unwrap();
+        let loaded: TilePalette = ron::from_str(&text).unwrap();
+        assert_eq!(loaded.recent(1), palette.recent(1));
+        assert_eq!(loaded.favorites(1), &[10]);
+    }
+
+
+    #[test]
+    fn test_filter() {
+        assert!(matches_filter("Crystal Cluster blue", "crystal"));
+        assert!(matches_filter("Crystal Cluster blue", "BLUE cryst"));
+        assert!(matches_filter("pipe", ""));
+        assert!(!matches_filter("Crystal Cluster blue", "crystal red"));
+    }
+}
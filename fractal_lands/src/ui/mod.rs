@@ -25,6 +25,7 @@ use crate::gl_support::draw_texture_clip;
 #[derive(PartialEq, Clone, Debug)]
 pub enum MouseButton {
     Left,
+    Middle,
     Right,
 }
 
@@ -123,6 +124,13 @@ pub trait UiController {
     fn update(&mut self, _appdata: &mut Self::Appdata, _dt: f64) {
 
     }
+
+    /**
+     * @return true if the controller has a component of its own which takes all keyboard input
+     */
+    fn has_keyboard_focus(&self) -> bool {
+        false
+    }
 }
 
 
@@ -223,7 +231,7 @@ impl UI {
     }
     
 
-    pub fn make_button(&self, x: i32, y: i32, w: i32, h: i32, label: &str, _id: usize) -> UiComponent {
+    pub fn make_button(&self, x: i32, y: i32, w: i32, h: i32, label: &str, id: usize) -> UiComponent {
         let button = UiButton {
             area: UiArea {
                 x, 
@@ -233,6 +241,7 @@ impl UI {
             }, 
             font: self.context.font_14.clone(),
             label: label.to_string(),    
+            id,
         };
         
         UiComponent {
@@ -241,6 +250,47 @@ impl UI {
     }
 
 
+    pub fn make_label(&self, x: i32, y: i32, w: i32, h: i32, label: &str) -> UiComponent {
+        let label = UiLabel {
+            area: UiArea {
+                x, 
+                y,
+                w,
+                h,                
+            }, 
+            font: self.context.font_14.clone(),
+            label: label.to_string(),    
+        };
+        
+        UiComponent {
+            head: Box::new(label),
+        }        
+    }
+
+
+    /**
+     * Makes a single line text input. The field has the keyboard focus right away.
+     */
+    pub fn make_text_field(&self, x: i32, y: i32, w: i32, h: i32, text: &str, id: usize) -> UiComponent {
+        let field = UiTextField {
+            area: UiArea {
+                x, 
+                y,
+                w,
+                h,                
+            }, 
+            font: self.context.font_14.clone(),
+            text: text.to_string(),
+            id,
+            focused: true,
+        };
+        
+        UiComponent {
+            head: Box::new(field),
+        }        
+    }
+
+
     pub fn make_icon(&self, x: i32, y: i32, w: i32, h: i32, 
                      tile: &Rc<Tile>, label: &str, id: usize) -> UiComponent {
         let icon = UiIcon {
@@ -301,11 +351,25 @@ impl UI {
 
 
     pub fn handle_button_event(&mut self, event: &ButtonEvent) -> Option<&dyn UiHead> {
+        self.update_input_state(event);
+        self.root.head.handle_button_event(event)
+    }
+
+
+    /**
+     * Tracks modifier keys and mouse buttons. This must see all button events,
+     * also those which are handled by components outside of the UI tree.
+     */
+    pub fn update_input_state(&mut self, event: &ButtonEvent) {
         if event.args.state == ButtonState::Press {
             if event.args.button == Button::Keyboard(Key::Named(NamedKey::Shift)) {
                 println!("Shift pressed");
                 self.context.keyboard_state.shift_pressed = true;
             }
+
+            if event.args.button == Button::Keyboard(Key::Named(NamedKey::Control)) {
+                self.context.keyboard_state.ctrl_pressed = true;
+            }
             
             if event.args.button == Button::Mouse(MouseButton::Left) {
                 self.context.mouse_state.left_pressed = true;
@@ -318,12 +382,22 @@ impl UI {
                 self.context.keyboard_state.shift_pressed = false;
             }    
 
+            if event.args.button == Button::Keyboard(Key::Named(NamedKey::Control)) {
+                self.context.keyboard_state.ctrl_pressed = false;
+            }
+
             if event.args.button == Button::Mouse(MouseButton::Left) {
                 self.context.mouse_state.left_pressed = false;
             }
         }
+    }
 
-        self.root.head.handle_button_event(event)
+
+    /**
+     * @return true if a component takes all keyboard input at the moment
+     */
+    pub fn has_keyboard_focus(&self) -> bool {
+        self.root.head.has_focus()
     }
 
 
@@ -389,6 +463,17 @@ pub trait UiHead {
     fn clear(&mut self) {
     }
 
+    /**
+     * Components with the keyboard focus get all keyboard events,
+     * no matter where the mouse pointer is.
+     */
+    fn has_focus(&self) -> bool {
+        false
+    }
+
+    fn set_focus(&mut self, _focus: bool) {
+    }
+
     fn get_id(&self) -> usize {
         0
     }
@@ -396,6 +481,10 @@ pub trait UiHead {
     fn get_numeric_result(&self) -> Vec<u32> {
         vec![0]
     }
+
+    fn get_text_result(&self) -> String {
+        String::new()
+    }
 }
 
 
@@ -469,10 +558,28 @@ impl UiHead for UiContainer {
 
     fn handle_button_event(&mut self, event: &ButtonEvent) -> Option<&dyn UiHead> {
 
+        if let Button::Keyboard(_) = event.args.button {
+            let focus = self.children.iter().position(|child| child.head.has_focus());
+
+            if let Some(child) = focus {
+                let c = &mut self.children[child];
+                return c.head.handle_button_event(&event.translate(-self.area.x, -self.area.y));
+            }
+        }
+
         let option = self.find_child_at(event.mx as i32, event.my as i32);
                 
         println!("event received at {}, {} -> child={}", event.mx, event.my, option.is_some());
 
+        // a click moves the keyboard focus to the clicked component
+        if event.args.button == Button::Mouse(MouseButton::Left) && event.args.state == ButtonState::Press {
+            for (i, child) in self.children.iter_mut().enumerate() {
+                if Some(i) != option {
+                    child.head.set_focus(false);
+                }
+            }
+        }
+
         match option {
             None => {
             },
@@ -528,6 +635,21 @@ impl UiHead for UiContainer {
     fn clear(&mut self) {
         self.children.clear();
     }
+
+
+    fn has_focus(&self) -> bool {
+        self.children.iter().any(|child| child.head.has_focus())
+    }
+
+
+    fn set_focus(&mut self, focus: bool) {
+        // only the focus can be taken away, a container doesn't know which child should get it
+        if !focus {
+            for child in self.children.iter_mut() {
+                child.head.set_focus(false);
+            }
+        }
+    }
 }
 
 
@@ -535,6 +657,7 @@ pub struct UiButton {
     pub area: UiArea,
     pub font: Rc<UiFont>,
     pub label: String,
+    pub id: usize,
 }
 
 
@@ -560,11 +683,151 @@ impl UiHead for UiButton {
             &context.scissors);
 
         let label_width = self.font.calc_string_width(&self.label) as i32;
-        let label_x = x + (area.w - label_width) / 2;
-        let label_y = y + (area.h - self.font.lineheight) / 2;
+        let label_x = x + area.x + (area.w - label_width) / 2;
+        let label_y = y + area.y + (area.h - self.font.lineheight) / 2;
 
         self.font.draw(display, target, program, label_x, label_y, &self.label, &[1.0, 1.0, 1.0, 1.0]);
     } 
+
+
+    fn handle_button_event(&mut self, event: &ButtonEvent) -> Option<&dyn UiHead> {
+        if event.args.button == Button::Mouse(MouseButton::Left) {
+            return Some(self);
+        }
+
+        None
+    }
+
+
+    fn get_id(&self) -> usize {
+        self.id
+    }
+}
+
+
+pub struct UiLabel {
+    pub area: UiArea,
+    pub font: Rc<UiFont>,
+    pub label: String,
+}
+
+
+impl UiHead for UiLabel {
+    
+    fn area(&self) -> &UiArea {
+        &self.area
+    }
+
+    fn draw(&self, display: &Display<WindowSurface>, target: &mut Frame, program: &Program, 
+            _context: &mut UiContext, x: i32, y: i32) {
+
+        let area = self.area();
+        let label_y = y + area.y + (area.h - self.font.lineheight) / 2;
+
+        self.font.draw(display, target, program, x + area.x, label_y, &self.label, &[1.0, 1.0, 1.0, 1.0]);
+    } 
+
+
+    fn handle_button_event(&mut self, _event: &ButtonEvent) -> Option<&dyn UiHead> {
+        None
+    }
+}
+
+
+pub struct UiTextField {
+    pub area: UiArea,
+    pub font: Rc<UiFont>,
+    pub text: String,
+    pub id: usize,
+    focused: bool,
+}
+
+
+impl UiHead for UiTextField {
+    
+    fn area(&self) -> &UiArea {
+        &self.area
+    }
+
+    fn draw(&self, display: &Display<WindowSurface>, target: &mut Frame, program: &Program, 
+            context: &mut UiContext, x: i32, y: i32) {
+
+        let area = self.area();
+        
+        draw_texture_clip(display, target, program,
+            BlendMode::Blend,
+            &context.tex_white,
+            (area.x + x) as f32,
+            (area.y + y) as f32, 
+            area.w as f32 / 16.0, 
+            area.h as f32 / 16.0,
+            &[0.05, 0.05, 0.05, 1.0],
+            &context.scissors);
+
+        // a trailing underscore marks the cursor
+        let text = if self.focused { self.text.to_string() + "_" } else { self.text.to_string() };
+        let text_y = y + area.y + (area.h - self.font.lineheight) / 2;
+
+        self.font.draw(display, target, program, x + area.x + 6, text_y, &text, &[1.0, 1.0, 1.0, 1.0]);
+    } 
+
+
+    /**
+     * Text is edited on key press, so that held keys repeat. All keyboard events
+     * are consumed while the field has the focus, so they don't trigger hotkeys.
+     */
+    fn handle_button_event(&mut self, event: &ButtonEvent) -> Option<&dyn UiHead> {
+
+        match &event.args.button {
+            Button::Mouse(MouseButton::Left) => {
+                self.focused = true;
+                Some(self)
+            },
+            Button::Mouse(_) => None,
+            Button::Keyboard(key) => {
+                if !self.focused {
+                    return None;
+                }
+
+                if event.args.state == ButtonState::Press {
+                    match key {
+                        Key::Character(chars) => {
+                            self.text.extend(chars.chars().filter(|c| !c.is_control()));
+                        },
+                        Key::Named(NamedKey::Space) => {
+                            self.text.push(' ');
+                        },
+                        Key::Named(NamedKey::Backspace) => {
+                            self.text.pop();
+                        },
+                        _ => {}
+                    }
+                }
+
+                Some(self)
+            }
+        }
+    }
+
+
+    fn has_focus(&self) -> bool {
+        self.focused
+    }
+
+
+    fn set_focus(&mut self, focus: bool) {
+        self.focused = focus;
+    }
+
+
+    fn get_id(&self) -> usize {
+        self.id
+    }
+
+
+    fn get_text_result(&self) -> String {
+        self.text.to_string()
+    }
 }
 
 
@@ -629,7 +892,8 @@ impl UiHead for UiIcon
     fn handle_button_event(&mut self, event: &ButtonEvent) -> Option<&dyn UiHead> {
 
         // which buttons should trigger this icon?
-        if event.args.button == Button::Mouse(MouseButton::Left) {
+        if event.args.button == Button::Mouse(MouseButton::Left) ||
+           event.args.button == Button::Mouse(MouseButton::Right) {
             return Some(self);
         }
 
@@ -711,6 +975,16 @@ impl UiHead for UiScrollpane
 
         self.child.head.handle_button_event(&event.translate(-self.area.x-self.offset_x, -self.area.y-self.offset_y))
     }
+
+
+    fn has_focus(&self) -> bool {
+        self.child.head.has_focus()
+    }
+
+
+    fn set_focus(&mut self, focus: bool) {
+        self.child.head.set_focus(focus);
+    }
 }
 
 